@@ -1,10 +1,14 @@
 fn main() {
     let proto_file = "proto/log.proto";
+    // served by gRPC reflection
+    let descriptor_path =
+        std::path::PathBuf::from(std::env::var("OUT_DIR").unwrap()).join("log_descriptor.bin");
     tonic_build::configure()
+        .file_descriptor_set_path(descriptor_path)
         .type_attribute("Record", "#[serde_as]")
         .type_attribute("Record", "#[derive(serde::Deserialize, serde::Serialize)]")
-        .field_attribute("value", r#"#[serde_as(as = "Base64")]"#)
-        .field_attribute("offset", r#"#[serde(skip_deserializing)]"#)
+        .field_attribute("Record.value", r#"#[serde_as(as = "Base64")]"#)
+        .field_attribute("Record.offset", r#"#[serde(skip_deserializing)]"#)
         .compile(&[proto_file], &["."])
         .unwrap_or_else(|e| panic!("protobuf compile error: {}", e));
 }
@@ -0,0 +1,19 @@
+use std::fmt::Debug;
+
+/// Picks the offset of each record appended to a segment.
+///
+/// Offsets must keep increasing, so `assign` returns a value `>= next_offset`,
+/// which is one past the highest offset already in the segment.
+pub trait OffsetAssigner: Debug + Send + Sync {
+    fn assign(&self, next_offset: u64) -> u64;
+}
+
+/// The default assigner: every record gets the next offset, with no gaps.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Sequential;
+
+impl OffsetAssigner for Sequential {
+    fn assign(&self, next_offset: u64) -> u64 {
+        next_offset
+    }
+}
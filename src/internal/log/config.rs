@@ -1,6 +1,341 @@
-#[derive(Default, Debug, Clone, Copy)]
+use std::{
+    io::{Error, ErrorKind},
+    path::{Path, PathBuf},
+    str::FromStr,
+    time::Duration,
+};
+
+use byteorder::{BigEndian, ByteOrder, LittleEndian};
+use serde::{Deserialize, Serialize};
+
+use super::{index::configured_widths, transform::Transforms};
+
+#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
 pub struct Config {
     pub max_store_bytes: u64,
     pub max_index_bytes: u64,
     pub initial_offset: u64,
+    /// Size of the buffer backing the store's streaming `Read` impl; 0 reads directly from the file.
+    pub read_buffer_bytes: u64,
+    /// Store bytes written between index entries; 0 indexes every record.
+    pub index_interval_bytes: u64,
+    /// Open existing segments for reading only, never creating, growing or writing files.
+    pub read_only: bool,
+    /// How often a background flusher writes dirty index pages back to disk.
+    pub index_flush_interval: Option<Duration>,
+    /// Seal the active segment once no record has been appended to it for this long.
+    pub seal_idle_after: Option<Duration>,
+    /// Roll the active segment once its oldest record is this old, however busy it is.
+    /// Appends check it too, so a log without background tasks still rolls.
+    pub max_segment_age: Option<Duration>,
+    /// Total store bytes `Log::enforce_retention` trims the log down to, removing the
+    /// oldest segments first.
+    pub retention_max_bytes: Option<u64>,
+    /// Age past which `Log::enforce_retention` removes a segment, counted from its newest
+    /// record.
+    pub retention_max_age: Option<Duration>,
+    /// How often background tasks run `Log::enforce_retention`.
+    pub retention_interval: Option<Duration>,
+    /// Directory of a second log that every append is copied to.
+    pub mirror_dir: Option<PathBuf>,
+    pub mirror_policy: MirrorPolicy,
+    /// Reopen a closed segment when a read lands in it instead of failing with `LogError::SegmentClosed`.
+    pub reopen_closed_segments: bool,
+    /// Fsync the log directory after creating or removing segment files, so the change
+    /// survives a crash.
+    pub sync_dir: bool,
+    /// Offsets a segment must span before a full store rolls it, unless its last record
+    /// alone is bigger than the store max. A full index always rolls.
+    pub min_records_per_segment: u64,
+    /// Fault in every page of a new index mapping up front rather than on its first write.
+    pub pretouch_index: bool,
+    /// Check on open that an index is a whole number of entries with increasing offsets,
+    /// failing with `ErrorKind::InvalidData` otherwise.
+    pub validate_on_open: bool,
+    /// Cut a record the store only holds part of, as a crash mid-append leaves it, and
+    /// the index entries past it on open, instead of failing to reopen the segment.
+    pub recover_on_open: bool,
+    /// Bytes of each index entry's relative offset, up to 8; 0 uses the default of 4.
+    /// An index records its widths when created and fails to open with any others.
+    pub off_width: u8,
+    /// Bytes of each index entry's store position, up to 8; 0 uses the default of 8.
+    pub pos_width: u8,
+    /// Create the log directory if it doesn't exist yet.
+    pub create_dir: bool,
+    /// Byte order of store frame headers and index entries. A log records its byte order
+    /// when created and fails to open with any other.
+    pub byte_order: ByteOrderKind,
+    /// Pipeline every record's bytes go through in the store. A log records the names of
+    /// its pipeline when created and fails to open with any other.
+    #[serde(skip)]
+    pub transforms: Transforms,
+}
+
+impl Config {
+    /// Reads a config from a JSON file if `path` ends in `.json` and from TOML otherwise.
+    /// Fields the file leaves out keep their defaults.
+    pub fn from_file<P: AsRef<Path>>(path: P) -> std::io::Result<Self> {
+        let path = path.as_ref();
+        let contents = std::fs::read_to_string(path)?;
+        let config = match path.extension().and_then(|ext| ext.to_str()) {
+            Some("json") => serde_json::from_str(&contents).map_err(|err| err.to_string()),
+            _ => toml::from_str(&contents).map_err(|err| err.to_string()),
+        };
+        config.map_err(|err| {
+            Error::new(
+                ErrorKind::InvalidData,
+                format!("invalid config {}: {}", path.display(), err),
+            )
+        })
+    }
+
+    /// Starts a [`ConfigBuilder`] with the store and index limits `Log::new` falls back to.
+    pub fn builder() -> ConfigBuilder {
+        ConfigBuilder {
+            config: Config {
+                max_store_bytes: 1024,
+                max_index_bytes: 1024,
+                ..Default::default()
+            },
+        }
+    }
+}
+
+/// Builds a [`Config`], checking the limits fit together before handing it out.
+#[derive(Debug, Clone)]
+pub struct ConfigBuilder {
+    config: Config,
+}
+
+impl ConfigBuilder {
+    pub fn max_store_bytes(mut self, bytes: u64) -> Self {
+        self.config.max_store_bytes = bytes;
+        self
+    }
+
+    pub fn max_index_bytes(mut self, bytes: u64) -> Self {
+        self.config.max_index_bytes = bytes;
+        self
+    }
+
+    /// Sets the bytes of each index entry's relative offset and store position; 0 keeps
+    /// the default.
+    pub fn index_widths(mut self, off_width: u8, pos_width: u8) -> Self {
+        self.config.off_width = off_width;
+        self.config.pos_width = pos_width;
+        self
+    }
+
+    pub fn initial_offset(mut self, offset: u64) -> Self {
+        self.config.initial_offset = offset;
+        self
+    }
+
+    pub fn retention_max_bytes(mut self, bytes: u64) -> Self {
+        self.config.retention_max_bytes = Some(bytes);
+        self
+    }
+
+    pub fn retention_max_age(mut self, age: Duration) -> Self {
+        self.config.retention_max_age = Some(age);
+        self
+    }
+
+    /// Fails with `ErrorKind::InvalidInput` if the store can't hold a byte, the index
+    /// widths are over 8 bytes, or the index can't hold a single entry of those widths.
+    pub fn build(self) -> std::io::Result<Config> {
+        let c = self.config;
+        if c.max_store_bytes == 0 {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                "max_store_bytes must be above 0",
+            ));
+        }
+        let (off_width, pos_width) = configured_widths(&c)?;
+        let entry_width = (off_width + pos_width) as u64;
+        if c.max_index_bytes < entry_width {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!(
+                    "max_index_bytes {} is below the entry width {}",
+                    c.max_index_bytes, entry_width
+                ),
+            ));
+        }
+        Ok(c)
+    }
+}
+
+/// What an append does when copying the record to the mirror fails.
+#[derive(Default, Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum MirrorPolicy {
+    /// Count the failure in `Log::mirror_misses` and carry on; the mirror is left with a gap
+    /// at that offset.
+    #[default]
+    Continue,
+    /// Fail the append. The record is already in the primary log.
+    Fail,
+}
+
+/// Byte order of the integers the store and index write to disk.
+#[derive(Default, Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ByteOrderKind {
+    /// The order of logs written before it could be chosen.
+    #[default]
+    BigEndian,
+    /// Spares little-endian hosts a byte swap per integer.
+    LittleEndian,
+}
+
+impl ByteOrderKind {
+    pub fn read_u32(self, buf: &[u8]) -> u32 {
+        match self {
+            Self::BigEndian => BigEndian::read_u32(buf),
+            Self::LittleEndian => LittleEndian::read_u32(buf),
+        }
+    }
+
+    pub fn read_u64(self, buf: &[u8]) -> u64 {
+        match self {
+            Self::BigEndian => BigEndian::read_u64(buf),
+            Self::LittleEndian => LittleEndian::read_u64(buf),
+        }
+    }
+
+    /// Reads an unsigned integer as wide as `buf`, at most 8 bytes.
+    pub fn read_uint(self, buf: &[u8]) -> u64 {
+        match self {
+            Self::BigEndian => BigEndian::read_uint(buf, buf.len()),
+            Self::LittleEndian => LittleEndian::read_uint(buf, buf.len()),
+        }
+    }
+
+    pub fn write_u32(self, buf: &mut [u8], n: u32) {
+        match self {
+            Self::BigEndian => BigEndian::write_u32(buf, n),
+            Self::LittleEndian => LittleEndian::write_u32(buf, n),
+        }
+    }
+
+    pub fn write_u64(self, buf: &mut [u8], n: u64) {
+        match self {
+            Self::BigEndian => BigEndian::write_u64(buf, n),
+            Self::LittleEndian => LittleEndian::write_u64(buf, n),
+        }
+    }
+
+    /// Writes `n` as an unsigned integer as wide as `buf`, which `n` must fit in.
+    pub fn write_uint(self, buf: &mut [u8], n: u64) {
+        match self {
+            Self::BigEndian => BigEndian::write_uint(buf, n, buf.len()),
+            Self::LittleEndian => LittleEndian::write_uint(buf, n, buf.len()),
+        }
+    }
+}
+
+impl std::fmt::Display for ByteOrderKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::BigEndian => write!(f, "big_endian"),
+            Self::LittleEndian => write!(f, "little_endian"),
+        }
+    }
+}
+
+impl FromStr for ByteOrderKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "big_endian" => Ok(Self::BigEndian),
+            "little_endian" => Ok(Self::LittleEndian),
+            _ => Err(format!("unknown byte order {:?}", s)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::log::index::ENTWIDTH;
+
+    #[test]
+    fn test_config_builder() {
+        let c = Config::builder()
+            .max_store_bytes(4096)
+            .max_index_bytes(ENTWIDTH as u64)
+            .initial_offset(1000)
+            .retention_max_bytes(1 << 20)
+            .retention_max_age(Duration::from_secs(60))
+            .build()
+            .unwrap();
+        assert_eq!(4096, c.max_store_bytes);
+        assert_eq!(ENTWIDTH as u64, c.max_index_bytes);
+        assert_eq!(1000, c.initial_offset);
+        assert_eq!(Some(1 << 20), c.retention_max_bytes);
+        assert_eq!(Some(Duration::from_secs(60)), c.retention_max_age);
+
+        // narrower entries fit a smaller index
+        let c = Config::builder()
+            .index_widths(2, 2)
+            .max_index_bytes(4)
+            .build()
+            .unwrap();
+        assert_eq!((2, 2), (c.off_width, c.pos_width));
+
+        let c = Config::builder().build().unwrap();
+        assert_eq!(1024, c.max_store_bytes);
+        assert_eq!(1024, c.max_index_bytes);
+
+        for builder in [
+            Config::builder().max_store_bytes(0),
+            Config::builder().max_index_bytes(ENTWIDTH as u64 - 1),
+            Config::builder().index_widths(8, 8).max_index_bytes(12),
+            Config::builder().index_widths(9, 0),
+        ] {
+            let err = builder.build().unwrap_err();
+            assert_eq!(ErrorKind::InvalidInput, err.kind());
+        }
+    }
+
+    #[test]
+    fn test_config_from_file() {
+        let dir = tempfile::Builder::new()
+            .prefix("config-test")
+            .tempdir()
+            .unwrap();
+        let toml_path = dir.path().join("proglog.toml");
+        std::fs::write(
+            &toml_path,
+            "max_store_bytes = 4096\n\
+             initial_offset = 1000\n\
+             byte_order = \"LittleEndian\"\n\
+             retention_max_age = { secs = 60, nanos = 0 }\n",
+        )
+        .unwrap();
+        let json_path = dir.path().join("proglog.json");
+        std::fs::write(
+            &json_path,
+            r#"{"max_store_bytes": 4096, "initial_offset": 1000,
+                "byte_order": "LittleEndian", "retention_max_age": {"secs": 60, "nanos": 0}}"#,
+        )
+        .unwrap();
+
+        for path in [toml_path, json_path] {
+            let c = Config::from_file(&path).unwrap();
+            assert_eq!(4096, c.max_store_bytes);
+            assert_eq!(1000, c.initial_offset);
+            assert_eq!(ByteOrderKind::LittleEndian, c.byte_order);
+            assert_eq!(Some(Duration::from_secs(60)), c.retention_max_age);
+            // missing fields keep their defaults
+            assert_eq!(0, c.max_index_bytes);
+            assert_eq!(None, c.retention_max_bytes);
+        }
+
+        let bad = dir.path().join("bad.toml");
+        std::fs::write(&bad, "max_store_bytes = \"lots\"\n").unwrap();
+        let err = Config::from_file(&bad).unwrap_err();
+        assert_eq!(ErrorKind::InvalidData, err.kind());
+    }
 }
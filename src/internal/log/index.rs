@@ -4,90 +4,402 @@ use std::{
     path::{Path, PathBuf},
 };
 
-use byteorder::{BigEndian, ByteOrder};
-use memmap2::MmapMut;
+use memmap2::{Mmap, MmapMut};
 
-use super::config::Config;
+use super::config::{ByteOrderKind, Config};
 
+/// Default entry layout, and the only one of indexes written before the header.
 pub const OFFWIDTH: usize = 4;
 pub const POSWIDTH: usize = 8;
 pub const ENTWIDTH: usize = OFFWIDTH + POSWIDTH;
 
+// An index created with a header starts with its magic, the header version and the
+// widths of its entries' offsets and positions, padded to the header width. Indexes
+// without the magic predate the header and hold `OFFWIDTH`/`POSWIDTH` entries from byte 0.
+const MAGIC: &[u8; 4] = b"PLIX";
+const HEADER_VERSION: u8 = 1;
+pub const HDRWIDTH: usize = 8;
+
+// the smallest common page size; touching at this stride covers larger pages too
+const PAGE_SIZE: usize = 4096;
+
+#[derive(Debug)]
+enum IndexMap {
+    ReadOnly(Mmap),
+    ReadWrite(MmapMut),
+}
+
+impl std::ops::Deref for IndexMap {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        match self {
+            IndexMap::ReadOnly(mmap) => mmap,
+            IndexMap::ReadWrite(mmap) => mmap,
+        }
+    }
+}
+
 #[derive(Debug)]
 pub struct Index {
     file: File,
-    mmap: MmapMut,
+    mmap: IndexMap,
+    // bytes taken by entries, after the header
     size: usize,
     path: PathBuf,
+    // entries written since the last flush
+    dirty: bool,
+    byte_order: ByteOrderKind,
+    // hard ceiling on the entry bytes the mapping may grow to hold
+    max_bytes: u64,
+    pretouch: bool,
+    // bytes before the first entry: 0 for indexes without a header
+    header: usize,
+    off_width: usize,
+    pos_width: usize,
 }
 
 impl Index {
     pub fn new<P: AsRef<Path>>(f: File, c: Config, path: P) -> std::io::Result<Self> {
-        let fi = f.metadata()?;
-        let size = fi.len() as usize;
-        f.set_len(c.max_index_bytes)?;
-        let mmap = unsafe { MmapMut::map_mut(&f)? };
+        let (off_width, pos_width) = configured_widths(&c)?;
+        let len = f.metadata()?.len() as usize;
+        let mmap = if c.read_only {
+            IndexMap::ReadOnly(unsafe { Mmap::map(&f)? })
+        } else {
+            // start at a page and double on demand, see `grow`
+            let initial = (PAGE_SIZE as u64)
+                .min(HDRWIDTH as u64 + c.max_index_bytes)
+                .max(len as u64);
+            f.set_len(initial)?;
+            let mut mmap = unsafe { MmapMut::map_mut(&f)? };
+            if c.pretouch_index {
+                pretouch(&mut mmap);
+            }
+            IndexMap::ReadWrite(mmap)
+        };
         let path = path.as_ref().to_path_buf();
-        let idx = Index {
+        let mut idx = Index {
             file: f,
             mmap,
-            size,
+            size: len,
             path,
+            dirty: false,
+            byte_order: c.byte_order,
+            max_bytes: c.max_index_bytes,
+            pretouch: c.pretouch_index,
+            header: 0,
+            off_width,
+            pos_width,
         };
+        let opened = idx.load_header(len).and_then(|()| {
+            if c.validate_on_open {
+                idx.validate()
+            } else {
+                Ok(())
+            }
+        });
+        if let Err(err) = opened {
+            // leave the file as it was found, unmapping first as windows can't resize
+            // a mapped file
+            let Index { file, mmap, .. } = idx;
+            drop(mmap);
+            if !c.read_only {
+                file.set_len(len as u64)?;
+            }
+            return Err(err);
+        }
         Ok(idx)
     }
 
+    // writes the header to a new index, or checks an existing one's entry widths
+    // against the configured ones
+    fn load_header(&mut self, len: usize) -> std::io::Result<()> {
+        let want = (self.off_width, self.pos_width);
+        let found = if self.mmap.starts_with(MAGIC) {
+            if len < HDRWIDTH || self.mmap[MAGIC.len()] != HEADER_VERSION {
+                return Err(Error::new(
+                    ErrorKind::InvalidData,
+                    format!("index {} has a bad header", self.path.display()),
+                ));
+            }
+            self.header = HDRWIDTH;
+            self.size = len - HDRWIDTH;
+            (
+                self.mmap[MAGIC.len() + 1] as usize,
+                self.mmap[MAGIC.len() + 2] as usize,
+            )
+        } else if len == 0 {
+            if let IndexMap::ReadWrite(ref mut mmap) = self.mmap {
+                mmap[..MAGIC.len()].copy_from_slice(MAGIC);
+                mmap[MAGIC.len()] = HEADER_VERSION;
+                mmap[MAGIC.len() + 1] = self.off_width as u8;
+                mmap[MAGIC.len() + 2] = self.pos_width as u8;
+                self.header = HDRWIDTH;
+                self.dirty = true;
+            }
+            want
+        } else {
+            (OFFWIDTH, POSWIDTH)
+        };
+        if found != want {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!(
+                    "index {} has {}-byte offsets and {}-byte positions, not the configured {} and {}",
+                    self.path.display(),
+                    found.0,
+                    found.1,
+                    want.0,
+                    want.1
+                ),
+            ));
+        }
+        Ok(())
+    }
+
+    // checks that the index is a whole number of entries, each with an offset above
+    // the one before it
+    fn validate(&self) -> std::io::Result<()> {
+        if !self.size.is_multiple_of(self.entwidth()) {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!("index size {} is not a whole number of entries", self.size),
+            ));
+        }
+        let mut prev = None;
+        for entry in 0..self.size / self.entwidth() {
+            let offset = self.entry(entry).0;
+            if prev.is_some_and(|prev| offset <= prev) {
+                return Err(Error::new(
+                    ErrorKind::InvalidData,
+                    format!(
+                        "index entry {} has offset {}, not above the previous {}",
+                        entry,
+                        offset,
+                        prev.unwrap_or_default()
+                    ),
+                ));
+            }
+            prev = Some(offset);
+        }
+        Ok(())
+    }
+
     pub fn close(self) -> std::io::Result<()> {
-        self.mmap.flush()?;
-        self.file.sync_all()?;
-        self.file.set_len(self.size as u64)?;
-        drop(self);
+        let Index {
+            file,
+            mmap,
+            size,
+            header,
+            ..
+        } = self;
+        if let IndexMap::ReadWrite(mmap) = mmap {
+            mmap.flush()?;
+            drop(mmap);
+            file.sync_all()?;
+            file.set_len((header + size) as u64)?;
+        }
         Ok(())
     }
 
-    pub fn read(&self, inp: i64) -> std::io::Result<(u32, u64)> {
+    pub fn read(&self, inp: i64) -> std::io::Result<(u64, u64)> {
         if self.size == 0 {
             return Err(Error::from(ErrorKind::UnexpectedEof));
         }
 
         let out = if inp == -1 {
-            ((self.size / ENTWIDTH) - 1) as u32
+            (self.size / self.entwidth()) - 1
         } else {
-            inp as u32
+            inp as usize
         };
 
-        let pos = out as usize * ENTWIDTH;
+        if self.size < (out + 1) * self.entwidth() {
+            return Err(Error::from(ErrorKind::UnexpectedEof));
+        }
 
-        if (self.size as usize) < pos + ENTWIDTH {
+        Ok(self.entry(out))
+    }
+
+    // decodes the nth entry, which must be in bounds
+    fn entry(&self, n: usize) -> (u64, u64) {
+        let pos = self.header + n * self.entwidth();
+        let mid = pos + self.off_width;
+        let offset = self.byte_order.read_uint(&self.mmap[pos..mid]);
+        let pos = self
+            .byte_order
+            .read_uint(&self.mmap[mid..(mid + self.pos_width)]);
+        (offset, pos)
+    }
+
+    /// Finds the entry with the largest offset `<= offset`.
+    pub fn find(&self, offset: u64) -> std::io::Result<(u64, u64)> {
+        let entries = self.entries_until(|entry| entry <= offset);
+        if entries == 0 {
             return Err(Error::from(ErrorKind::UnexpectedEof));
         }
+        self.read(entries as i64 - 1)
+    }
+
+    /// Counts the entries with offsets below `offset`, which is where the first one at or
+    /// past it sits.
+    pub fn entries_below(&self, offset: u64) -> usize {
+        self.entries_until(|entry| entry < offset)
+    }
+
+    // counts the leading entries whose offsets satisfy `pred`, which must hold for a prefix
+    fn entries_until(&self, pred: impl Fn(u64) -> bool) -> usize {
+        let (mut lo, mut hi) = (0usize, self.size / self.entwidth());
+        while lo < hi {
+            let mid = lo + (hi - lo) / 2;
+            if pred(self.entry(mid).0) {
+                lo = mid + 1;
+            } else {
+                hi = mid;
+            }
+        }
+        lo
+    }
+
+    pub fn write(&mut self, offset: u64, pos: u64) -> std::io::Result<()> {
+        if let IndexMap::ReadOnly(_) = self.mmap {
+            return Err(Error::from(ErrorKind::PermissionDenied));
+        }
+        for (name, n, width) in [
+            ("offset", offset, self.off_width),
+            ("position", pos, self.pos_width),
+        ] {
+            if width < 8 && n >> (8 * width) != 0 {
+                return Err(Error::new(
+                    ErrorKind::InvalidInput,
+                    format!("{} {} doesn't fit in {} bytes", name, n, width),
+                ));
+            }
+        }
+        let entwidth = self.entwidth();
+        if self.mmap.len() < self.header + self.size + entwidth {
+            self.grow()?;
+        }
+        let mmap = match self.mmap {
+            IndexMap::ReadWrite(ref mut mmap) => mmap,
+            IndexMap::ReadOnly(_) => return Err(Error::from(ErrorKind::PermissionDenied)),
+        };
 
-        let out = BigEndian::read_u32(&self.mmap[pos..(pos + OFFWIDTH)]);
-        let pos = BigEndian::read_u64(&self.mmap[(pos + OFFWIDTH)..(pos + ENTWIDTH)]);
-        Ok((out, pos))
+        let at = self.header + self.size;
+        let mid = at + self.off_width;
+        let order = self.byte_order;
+        order.write_uint(&mut mmap[at..mid], offset);
+        order.write_uint(&mut mmap[mid..(at + entwidth)], pos);
+        self.size += entwidth;
+        self.dirty = true;
+        Ok(())
     }
 
-    pub fn write(&mut self, offset: u32, pos: u64) -> std::io::Result<()> {
-        if self.mmap.len() < self.size as usize + ENTWIDTH {
+    // doubles the file and remaps it, up to `max_index_bytes` of entries
+    fn grow(&mut self) -> std::io::Result<()> {
+        let len = (self.mmap.len() as u64 * 2).min(self.header as u64 + self.max_bytes);
+        if len < (self.header + self.size + self.entwidth()) as u64 {
             return Err(Error::from(ErrorKind::UnexpectedEof));
         }
+        // windows can't resize a mapped file, so park an anonymous page in its place
+        // while the file grows; the old mapping shares the file's pages, so dropping it
+        // loses nothing
+        drop(std::mem::replace(
+            &mut self.mmap,
+            IndexMap::ReadWrite(MmapMut::map_anon(1)?),
+        ));
+        // remap even if the resize failed, at whatever length the file is left
+        let resized = self.file.set_len(len);
+        let mut mmap = unsafe { MmapMut::map_mut(&self.file)? };
+        if resized.is_ok() && self.pretouch {
+            pretouch(&mut mmap);
+        }
+        self.mmap = IndexMap::ReadWrite(mmap);
+        resized
+    }
 
-        BigEndian::write_u32(&mut self.mmap[self.size..(self.size + OFFWIDTH)], offset);
-        BigEndian::write_u64(
-            &mut self.mmap[(self.size + OFFWIDTH)..(self.size + ENTWIDTH)],
-            pos,
-        );
-        self.size += ENTWIDTH;
+    /// Drops the entries with offsets `>= offset`, zeroing the space they took.
+    pub fn truncate(&mut self, offset: u64) -> std::io::Result<()> {
+        let size = self.entries_until(|entry| entry < offset) * self.entwidth();
+        let mmap = match self.mmap {
+            IndexMap::ReadWrite(ref mut mmap) => mmap,
+            IndexMap::ReadOnly(_) => return Err(Error::from(ErrorKind::PermissionDenied)),
+        };
+        mmap[(self.header + size)..(self.header + self.size)].fill(0);
+        self.size = size;
+        self.dirty = true;
         Ok(())
     }
 
+    /// Writes dirty pages back to the file, returning whether there was anything to flush.
+    pub fn flush(&mut self) -> std::io::Result<bool> {
+        if !self.dirty {
+            return Ok(false);
+        }
+        if let IndexMap::ReadWrite(ref mmap) = self.mmap {
+            mmap.flush()?;
+        }
+        self.dirty = false;
+        Ok(true)
+    }
+
     pub fn get_path(&self) -> PathBuf {
-        return self.path.clone();
+        self.path.clone()
     }
 
+    /// Bytes taken by entries, not counting the header.
     #[inline]
     pub fn size(&self) -> u64 {
-        return self.size as u64;
+        self.size as u64
+    }
+
+    /// Bytes of the file holding the header and entries, short of the room mapped ahead.
+    #[inline]
+    pub fn file_size(&self) -> u64 {
+        (self.header + self.size) as u64
+    }
+
+    /// Bytes each entry takes.
+    #[inline]
+    pub fn entry_width(&self) -> u64 {
+        self.entwidth() as u64
+    }
+
+    #[inline]
+    fn entwidth(&self) -> usize {
+        self.off_width + self.pos_width
+    }
+}
+
+// the entry widths `c` asks for, with 0 meaning the default
+pub(super) fn configured_widths(c: &Config) -> std::io::Result<(usize, usize)> {
+    let widths = (
+        match c.off_width {
+            0 => OFFWIDTH,
+            n => n as usize,
+        },
+        match c.pos_width {
+            0 => POSWIDTH,
+            n => n as usize,
+        },
+    );
+    if widths.0 > 8 || widths.1 > 8 {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            format!(
+                "index entry widths {} and {} must be at most 8 bytes",
+                widths.0, widths.1
+            ),
+        ));
+    }
+    Ok(widths)
+}
+
+// writes each page's first byte back to itself, taking the write fault now
+fn pretouch(mmap: &mut MmapMut) {
+    for page in mmap.chunks_mut(PAGE_SIZE) {
+        let byte = &mut page[0] as *mut u8;
+        // volatile so the no-op store is not optimized away
+        unsafe { std::ptr::write_volatile(byte, std::ptr::read_volatile(byte)) };
     }
 }
 
@@ -104,14 +416,16 @@ mod tests {
             .prefix("index_test")
             .tempfile()
             .unwrap();
-        let mut config = Config::default();
-        config.max_index_bytes = 1024;
+        let config = Config {
+            max_index_bytes: 1024,
+            ..Default::default()
+        };
 
-        let mut idx = Index::new(file.reopen().unwrap(), config, file.path()).unwrap();
+        let mut idx = Index::new(file.reopen().unwrap(), config.clone(), file.path()).unwrap();
         let err = idx.read(-1).map_err(|e| e.kind());
         assert_eq!(file.path().to_path_buf(), idx.get_path());
         assert_eq!(err, Err(io::ErrorKind::UnexpectedEof));
-        let entries: Vec<(u32, u64)> = vec![(0, 0), (1, 10)];
+        let entries: Vec<(u64, u64)> = vec![(0, 0), (1, 10)];
         for want in entries.iter() {
             idx.write(want.0, want.1).unwrap();
             let (_, pos) = idx.read(want.0 as i64).unwrap();
@@ -127,4 +441,234 @@ mod tests {
         assert_eq!(offset, entries[1].0);
         assert_eq!(pos, entries[1].1);
     }
+
+    #[test]
+    fn test_index_truncate() {
+        let file = tempfile::Builder::new()
+            .append(true)
+            .prefix("index_truncate_test")
+            .tempfile()
+            .unwrap();
+        let config = Config {
+            max_index_bytes: 1024,
+            ..Default::default()
+        };
+
+        let mut idx = Index::new(file.reopen().unwrap(), config.clone(), file.path()).unwrap();
+        for i in 0..5u64 {
+            idx.write(i, i * 10).unwrap();
+        }
+        idx.truncate(3).unwrap();
+        assert_eq!((2, 20), idx.read(-1).unwrap());
+        assert_eq!(3 * ENTWIDTH as u64, idx.size());
+        assert!(idx.mmap[HDRWIDTH + 3 * ENTWIDTH..HDRWIDTH + 5 * ENTWIDTH]
+            .iter()
+            .all(|&b| b == 0));
+
+        // truncating past the last entry keeps them all
+        idx.truncate(10).unwrap();
+        assert_eq!((2, 20), idx.read(-1).unwrap());
+        idx.write(5, 50).unwrap();
+        idx.close().unwrap();
+
+        let mut idx = Index::new(file.reopen().unwrap(), config, file.path()).unwrap();
+        assert_eq!((5, 50), idx.read(-1).unwrap());
+        idx.truncate(0).unwrap();
+        assert_eq!(0, idx.size());
+    }
+
+    #[test]
+    fn test_index_validate_on_open() {
+        let config = Config {
+            max_index_bytes: 1024,
+            validate_on_open: true,
+            ..Default::default()
+        };
+        let index_file = |entries: &[u32], extra: &[u8]| {
+            let file = tempfile::Builder::new()
+                .prefix("index_validate_test")
+                .tempfile()
+                .unwrap();
+            let mut buf = Vec::new();
+            for (i, offset) in entries.iter().enumerate() {
+                buf.extend_from_slice(&offset.to_be_bytes());
+                buf.extend_from_slice(&(i as u64 * 10).to_be_bytes());
+            }
+            buf.extend_from_slice(extra);
+            std::fs::write(file.path(), buf).unwrap();
+            file
+        };
+        let open = |file: &tempfile::NamedTempFile, c: &Config| {
+            Index::new(file.reopen().unwrap(), c.clone(), file.path())
+                .map(|idx| idx.size())
+                .map_err(|e| (e.kind(), e.to_string()))
+        };
+
+        let file = index_file(&[0, 2, 5], &[]);
+        assert_eq!(Ok(3 * ENTWIDTH as u64), open(&file, &config));
+
+        let file = index_file(&[0, 2, 2, 5], &[]);
+        let (kind, msg) = open(&file, &config).unwrap_err();
+        assert_eq!(io::ErrorKind::InvalidData, kind);
+        assert!(msg.contains("entry 2"), "{}", msg);
+        assert_eq!(
+            4 * ENTWIDTH as u64,
+            file.as_file().metadata().unwrap().len()
+        );
+
+        let file = index_file(&[0, 2], &[0, 0, 0]);
+        let (kind, _) = open(&file, &config).unwrap_err();
+        assert_eq!(io::ErrorKind::InvalidData, kind);
+
+        // without the flag the index is trusted as is
+        let unchecked = Config {
+            validate_on_open: false,
+            ..config
+        };
+        let file = index_file(&[0, 2, 2, 5], &[]);
+        assert!(open(&file, &unchecked).is_ok());
+    }
+
+    #[test]
+    fn test_index_find() {
+        let file = tempfile::Builder::new()
+            .append(true)
+            .prefix("index_find_test")
+            .tempfile()
+            .unwrap();
+        let config = Config {
+            max_index_bytes: 1024,
+            ..Default::default()
+        };
+
+        let mut idx = Index::new(file.reopen().unwrap(), config.clone(), file.path()).unwrap();
+        let err = idx.find(0).map_err(|e| e.kind());
+        assert_eq!(err, Err(io::ErrorKind::UnexpectedEof));
+
+        let entries: Vec<(u64, u64)> = vec![(2, 0), (5, 40), (9, 90)];
+        for (offset, pos) in entries.iter() {
+            idx.write(*offset, *pos).unwrap();
+        }
+
+        let err = idx.find(1).map_err(|e| e.kind());
+        assert_eq!(err, Err(io::ErrorKind::UnexpectedEof));
+        assert_eq!((2, 0), idx.find(2).unwrap());
+        assert_eq!((2, 0), idx.find(4).unwrap());
+        assert_eq!((5, 40), idx.find(5).unwrap());
+        assert_eq!((5, 40), idx.find(8).unwrap());
+        assert_eq!((9, 90), idx.find(100).unwrap());
+    }
+
+    #[test]
+    fn test_index_grow() {
+        let file = tempfile::Builder::new()
+            .append(true)
+            .prefix("index_grow_test")
+            .tempfile()
+            .unwrap();
+        let entries = 1000u64;
+        let config = Config {
+            max_index_bytes: entries * ENTWIDTH as u64,
+            ..Default::default()
+        };
+
+        let mut idx = Index::new(file.reopen().unwrap(), config.clone(), file.path()).unwrap();
+        assert_eq!(PAGE_SIZE as u64, file.as_file().metadata().unwrap().len());
+        for i in 0..entries {
+            idx.write(i, i * 10).unwrap();
+        }
+        // capped at the ceiling rather than the next doubling
+        assert_eq!(
+            HDRWIDTH as u64 + config.max_index_bytes,
+            file.as_file().metadata().unwrap().len()
+        );
+        let err = idx.write(entries, 0).unwrap_err();
+        assert_eq!(io::ErrorKind::UnexpectedEof, err.kind());
+        for i in [0u64, 341, 999] {
+            assert_eq!((i, i * 10), idx.read(i as i64).unwrap());
+        }
+        idx.close().unwrap();
+
+        let idx = Index::new(file.reopen().unwrap(), config, file.path()).unwrap();
+        assert_eq!(entries * ENTWIDTH as u64, idx.size());
+        assert_eq!((999, 9990), idx.read(-1).unwrap());
+    }
+
+    #[test]
+    fn test_index_widths() {
+        let file = tempfile::Builder::new()
+            .append(true)
+            .prefix("index_widths_test")
+            .tempfile()
+            .unwrap();
+        let config = Config {
+            max_index_bytes: 1024,
+            off_width: 8,
+            pos_width: 4,
+            ..Default::default()
+        };
+
+        let mut idx = Index::new(file.reopen().unwrap(), config.clone(), file.path()).unwrap();
+        assert_eq!(12, idx.entry_width());
+        let big = u32::MAX as u64 + 1;
+        idx.write(0, 0).unwrap();
+        idx.write(big, 10).unwrap();
+        let err = idx.write(big + 1, u32::MAX as u64 + 1).unwrap_err();
+        assert_eq!(io::ErrorKind::InvalidInput, err.kind());
+        assert_eq!((big, 10), idx.find(big + 5).unwrap());
+        idx.close().unwrap();
+
+        let idx = Index::new(file.reopen().unwrap(), config.clone(), file.path()).unwrap();
+        assert_eq!((big, 10), idx.read(-1).unwrap());
+        idx.close().unwrap();
+
+        // the widths come from the header, so other widths can't open it
+        let err = Index::new(file.reopen().unwrap(), Config::default(), file.path()).unwrap_err();
+        assert_eq!(io::ErrorKind::InvalidInput, err.kind());
+        assert_eq!(
+            (HDRWIDTH + 2 * 12) as u64,
+            file.as_file().metadata().unwrap().len()
+        );
+
+        // an index from before the header only opens with the default widths
+        let legacy = tempfile::Builder::new()
+            .prefix("index_widths_test")
+            .tempfile()
+            .unwrap();
+        let mut buf = 7u32.to_be_bytes().to_vec();
+        buf.extend_from_slice(&70u64.to_be_bytes());
+        std::fs::write(legacy.path(), buf).unwrap();
+        let err = Index::new(legacy.reopen().unwrap(), config, legacy.path()).unwrap_err();
+        assert_eq!(io::ErrorKind::InvalidInput, err.kind());
+        let idx = Index::new(legacy.reopen().unwrap(), Config::default(), legacy.path()).unwrap();
+        assert_eq!((7, 70), idx.read(-1).unwrap());
+    }
+
+    #[test]
+    fn test_index_pretouch() {
+        let file = tempfile::Builder::new()
+            .append(true)
+            .prefix("index_pretouch_test")
+            .tempfile()
+            .unwrap();
+        let config = Config {
+            max_index_bytes: 3 * PAGE_SIZE as u64,
+            pretouch_index: true,
+            ..Default::default()
+        };
+
+        let mut idx = Index::new(file.reopen().unwrap(), config.clone(), file.path()).unwrap();
+        assert!(idx.read(-1).is_err());
+        for i in 0..600u64 {
+            idx.write(i, i * 10).unwrap();
+        }
+        idx.close().unwrap();
+
+        // pretouching an index with entries leaves them intact
+        let idx = Index::new(file.reopen().unwrap(), config, file.path()).unwrap();
+        assert_eq!(600 * ENTWIDTH as u64, idx.size());
+        for i in [0u64, 341, 599] {
+            assert_eq!((i, i * 10), idx.read(i as i64).unwrap());
+        }
+    }
 }
@@ -1,19 +1,104 @@
 use std::{
-    io::Read,
+    collections::{BTreeSet, HashMap},
+    fs::File,
+    io::{Read, Seek, SeekFrom},
+    ops::Range,
     path::{Path, PathBuf},
+    sync::{Arc, RwLock},
+    thread::JoinHandle,
+    time::{Duration, Instant, SystemTime},
 };
 
-use crate::server::log::Record;
+use serde::Serialize;
+use tokio::sync::Notify;
 
-use super::{config::Config, segment::Segment};
+use crate::server::log::{LogError, Record, Validator};
+
+use super::{
+    assigner::{OffsetAssigner, Sequential},
+    config::{ByteOrderKind, Config, MirrorPolicy},
+    merkle::{self, Hash},
+    reader::LogReader,
+    segment::{self, Checkpoint, IndexEntry, Segment},
+};
 use std::io::{Error, ErrorKind, Result};
 
+// file in the log directory recording the byte order its segments are written in
+const BYTE_ORDER_FILE: &str = "byte_order";
+const TRANSFORMS_FILE: &str = "transforms";
+
+/// An offset at which two logs disagree.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OffsetDiff {
+    /// Both logs hold a record at the offset, with different values.
+    ValueDiffers(u64),
+    /// Only the log `diff` was called on holds a record at the offset.
+    OnlyInSelf(u64),
+    /// Only the other log holds a record at the offset.
+    OnlyInOther(u64),
+}
+
+/// Where an appended record ended up.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AppendResult {
+    pub offset: u64,
+    /// Base offset of the segment the record was written to.
+    pub base_offset: u64,
+    /// Position of the record's length prefix in the segment's store.
+    pub position: u64,
+    /// Whether the append filled the segment, which rolled the log onto a new one.
+    pub rolled: bool,
+}
+
+/// A record the mirror failed to take while `MirrorPolicy::Continue` let the append through.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MirrorMiss {
+    /// Offset of the record the mirror is missing.
+    pub offset: u64,
+    /// Why the mirror append failed.
+    pub reason: String,
+}
+
+/// Handles on a sealed segment's files, for copying them out whole.
+#[derive(Debug)]
+pub struct SegmentSnapshot {
+    pub base_offset: u64,
+    pub next_offset: u64,
+    /// The store, with the bytes of it that hold the segment's records.
+    pub store: (File, u64),
+    /// The index, with the bytes of it that hold its header and entries.
+    pub index: (File, u64),
+}
+
+/// Bytes moved by the log's maintenance, summed over every merge and truncation.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct CompactionStats {
+    /// Segment bytes read to be rewritten.
+    pub bytes_read: u64,
+    /// Segment bytes written by rewrites.
+    pub bytes_written: u64,
+    /// Segment bytes freed by removing records.
+    pub bytes_reclaimed: u64,
+}
+
 pub struct Log {
     pub dir: PathBuf,
     pub config: Config,
     active_segment: usize,
     segments: Vec<Option<Segment>>,
     reader_idx: usize,
+    assigner: Arc<dyn OffsetAssigner>,
+    last_append: Instant,
+    mirror: Option<Box<Log>>,
+    // Merkle roots of sealed segments, keyed by base offset
+    segment_roots: HashMap<u64, Hash>,
+    compaction_stats: CompactionStats,
+    // mirror appends that failed under `MirrorPolicy::Continue`, and the latest of them
+    mirror_misses: u64,
+    last_mirror_miss: Option<MirrorMiss>,
+    validators: Vec<Validator>,
+    paused: bool,
+    appended: Arc<Notify>,
 }
 
 impl Log {
@@ -31,57 +116,685 @@ impl Log {
             active_segment: 0,
             segments: Vec::new(),
             reader_idx: 0,
+            assigner: Arc::new(Sequential),
+            last_append: Instant::now(),
+            mirror: None,
+            segment_roots: HashMap::new(),
+            compaction_stats: CompactionStats::default(),
+            mirror_misses: 0,
+            last_mirror_miss: None,
+            validators: Vec::new(),
+            paused: false,
+            appended: Arc::new(Notify::new()),
         };
 
+        l.check_dir()?;
+        l.check_byte_order()?;
+        l.check_transforms()?;
         l.setup()?;
+        if let (Some(mirror_dir), false) = (&l.config.mirror_dir, l.config.read_only) {
+            let mut mc = l.config.clone();
+            mc.mirror_dir = None;
+            std::fs::create_dir_all(mirror_dir)?;
+            l.mirror = Some(Box::new(Log::new(mirror_dir, mc)?));
+        }
         Ok(l)
     }
 
-    pub fn append(&mut self, record: Record) -> Result<Option<u64>> {
+    /// Opens an existing log without ever writing to its directory, e.g. on a read-only mount.
+    pub fn open_read_only<P: AsRef<Path>>(dir: P, mut c: Config) -> Result<Self> {
+        c.read_only = true;
+        Self::new(dir, c)
+    }
+
+    /// Replaces how offsets are picked for subsequent appends.
+    pub fn set_offset_assigner(&mut self, assigner: Arc<dyn OffsetAssigner>) {
+        for segment in self.segments.iter_mut().flatten() {
+            segment.set_offset_assigner(assigner.clone());
+        }
+        if let Some(ref mut mirror) = self.mirror {
+            mirror.set_offset_assigner(assigner.clone());
+        }
+        self.assigner = assigner;
+    }
+
+    /// Adds a validator to the chain consulted, in order, before every append.
+    pub fn set_validator(&mut self, v: Validator) {
+        self.validators.push(v);
+    }
+
+    /// Rejects appends with `LogError::Paused` until `resume`; reads are unaffected.
+    pub fn pause(&mut self) {
+        self.paused = true;
+    }
+
+    pub fn resume(&mut self) {
+        self.paused = false;
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused
+    }
+
+    /// Notified after every successful append, for readers waiting at the end of the log.
+    ///
+    /// Only waiters registered when an append lands are woken, so a reader should create
+    /// its `Notified` future before letting go of the log.
+    pub fn append_notify(&self) -> Arc<Notify> {
+        self.appended.clone()
+    }
+
+    /// Appends the record, returning its offset. Fails with `LogError::Closed` once the
+    /// log is closed.
+    pub fn append(&mut self, record: Record) -> Result<u64> {
+        Ok(self.append_detailed(record)?.offset)
+    }
+
+    /// Appends the record like `append`, also reporting which segment it landed in.
+    pub fn append_detailed(&mut self, record: Record) -> Result<AppendResult> {
+        self.check_append(&record)?;
+        let mirrored = self.mirror.as_ref().map(|_| record.clone());
+        let res = self.append_local(record)?;
+        self.appended.notify_waiters();
+        if let Some(record) = mirrored {
+            self.mirror_append(record, res.offset)?;
+        }
+        Ok(res)
+    }
+
+    /// Appends `records` in order, rolling segments as needed, and returns the range of
+    /// offsets they were given. The stores are flushed once, after the last record.
+    ///
+    /// Unlike `append_atomic`, records appended before a failure stay in the log.
+    pub fn append_batch(&mut self, records: Vec<Record>) -> Result<Range<u64>> {
+        let first = self.active_segment;
+        let next_offset = self.next_offset();
+        let mut range: Option<Range<u64>> = None;
+        for record in records {
+            let offset = self.append(record)?;
+            range.get_or_insert(offset..offset).end = offset + 1;
+        }
+        for segment in self.segments[first..].iter_mut().flatten() {
+            segment.flush()?;
+        }
+        Ok(range.unwrap_or(next_offset..next_offset))
+    }
+
+    /// Appends all of `records` at consecutive offsets, or none of them.
+    ///
+    /// If any append fails, the records already written are cut back out of the store and
+    /// index and any segments created along the way are removed. The mirror only receives
+    /// the records once they have all been stored.
+    pub fn append_atomic(&mut self, records: Vec<Record>) -> Result<Vec<u64>> {
+        self.ensure_writable()?;
+        for record in records.iter() {
+            self.check_append(record)?;
+        }
+        let mirrored = self.mirror.as_ref().map(|_| records.clone());
+        let (segments, active, last_append) =
+            (self.segments.len(), self.active_segment, self.last_append);
+        let checkpoint = match self.segments[active] {
+            Some(ref segment) => segment.checkpoint(),
+            None => return Err(Error::other("corrupted log")),
+        };
+
+        let mut offsets = Vec::with_capacity(records.len());
+        for record in records {
+            let err = match self.append_local(record) {
+                Ok(res) => {
+                    offsets.push(res.offset);
+                    continue;
+                }
+                Err(err) => err,
+            };
+            self.rollback(segments, active, checkpoint)?;
+            self.last_append = last_append;
+            return Err(err);
+        }
+
+        self.appended.notify_waiters();
+        if let Some(records) = mirrored {
+            for (record, &offset) in records.into_iter().zip(offsets.iter()) {
+                self.mirror_append(record, offset)?;
+            }
+        }
+        Ok(offsets)
+    }
+
+    // the `LogError` is kept inside the `io::Error` so callers can tell why
+    fn check_append(&self, record: &Record) -> Result<()> {
+        if self.paused {
+            return Err(LogError::Paused.into());
+        }
+        for validator in self.validators.iter() {
+            validator(record).map_err(|reason| LogError::Rejected { reason })?;
+        }
+        Ok(())
+    }
+
+    // undoes the appends since `checkpoint` was taken of the then active segment
+    fn rollback(&mut self, segments: usize, active: usize, checkpoint: Checkpoint) -> Result<()> {
+        for mut segment in self.segments.drain(segments..).flatten() {
+            segment.remove()?;
+        }
+        self.active_segment = active;
+        match self.segments[active] {
+            Some(ref mut segment) => segment.rollback(checkpoint),
+            None => Err(Error::other("corrupted log")),
+        }
+    }
+
+    fn append_local(&mut self, record: Record) -> Result<AppendResult> {
         let idx = self.active_segment;
         let segment = match self.segments[idx] {
             Some(ref mut segment) => segment,
-            None => return Ok(None),
-        };
-        let offset = match segment.append(record)? {
-            Some(offset) => offset,
-            None => return Ok(None),
+            None => return Err(LogError::Closed.into()),
         };
+        let (offset, position) = segment.append_with_position(record)?;
 
-        if segment.is_maxed() {
+        self.last_append = Instant::now();
+        let base_offset = segment.base_offset;
+        let rolled = segment.is_maxed();
+        if rolled {
             self.new_segment(offset + 1)?;
         }
+        Ok(AppendResult {
+            offset,
+            base_offset,
+            position,
+            rolled,
+        })
+    }
+
+    fn mirror_append(&mut self, record: Record, offset: u64) -> Result<()> {
+        let mirror = match self.mirror {
+            Some(ref mut mirror) => mirror,
+            None => return Ok(()),
+        };
+        if let Err(err) = mirror.append_mirrored(record, offset) {
+            match self.config.mirror_policy {
+                MirrorPolicy::Fail => return Err(err),
+                MirrorPolicy::Continue => {
+                    self.mirror_misses += 1;
+                    self.last_mirror_miss = Some(MirrorMiss {
+                        offset,
+                        reason: err.to_string(),
+                    });
+                }
+            }
+        }
+        Ok(())
+    }
 
-        Ok(Some(offset))
+    // appends a record the primary stored at `offset`, skipping past any it missed
+    fn append_mirrored(&mut self, record: Record, offset: u64) -> Result<()> {
+        let next_offset = match self.segments[self.active_segment] {
+            Some(ref segment) => segment.next_offset,
+            None => return Err(Error::other("mirror closed")),
+        };
+        if next_offset < offset {
+            self.advance_to(offset)?;
+        }
+        match self.append(record)? {
+            got if got == offset => Ok(()),
+            got => Err(Error::other(format!(
+                "mirror stored offset {} as {}",
+                offset, got
+            ))),
+        }
     }
 
     pub fn read_at_offset(&mut self, offset: u64) -> Result<Option<Record>> {
-        let s = match self.segments.iter_mut().find(|seg| {
-            if let Some(ref seg) = seg {
-                seg.base_offset <= offset && offset < seg.next_offset
-            } else {
-                false
-            }
-        }) {
+        let s = match self
+            .segments
+            .iter_mut()
+            .flatten()
+            .find(|seg| seg.base_offset <= offset && offset < seg.next_offset)
+        {
             None => {
                 return Err(Error::new(
-                    ErrorKind::Other,
+                    ErrorKind::NotFound,
                     format!("offset out of range: {}", offset),
                 ))
             }
-            Some(s) => match s {
-                Some(s) => s,
-                None => return Ok(None),
-            },
+            Some(s) => s,
         };
+        if s.is_closed() {
+            if !self.config.reopen_closed_segments {
+                return Err(LogError::SegmentClosed {
+                    base_offset: s.base_offset,
+                }
+                .into());
+            }
+            s.reopen()?;
+        }
         s.read_at_offset(offset)
     }
 
-    pub fn close(&mut self) -> Result<()> {
-        for segment in self.segments.iter_mut() {
-            if let Some(ref mut segment) = segment {
-                segment.close()?;
+    /// Returns up to `limit` index entries with offsets in `[from, to]`, in order, reading
+    /// only the segments' indexes and never their stores.
+    pub fn index_entries(&mut self, from: u64, to: u64, limit: usize) -> Result<Vec<IndexEntry>> {
+        let mut entries = Vec::new();
+        for segment in self.segments.iter_mut().flatten() {
+            if entries.len() >= limit || segment.base_offset > to {
+                break;
+            }
+            if segment.next_offset <= from {
+                continue;
+            }
+            if segment.is_closed() {
+                if !self.config.reopen_closed_segments {
+                    return Err(LogError::SegmentClosed {
+                        base_offset: segment.base_offset,
+                    }
+                    .into());
+                }
+                segment.reopen()?;
+            }
+            entries.extend(segment.index_entries(from, to, limit - entries.len())?);
+        }
+        Ok(entries)
+    }
+
+    /// Reads the records in `[from, to]`, clamping `to` to the highest offset and
+    /// skipping any gaps.
+    pub fn read_range(&mut self, from: u64, to: u64) -> Result<Vec<Record>> {
+        let mut records = Vec::new();
+        self.scan_range(from, to, |record| {
+            records.push(record);
+            true
+        })?;
+        Ok(records)
+    }
+
+    /// Reads the records in `[from, to]` one at a time like `read_range`, handing each to
+    /// `visit` until it returns false, so a caller can stop without loading the rest.
+    pub fn scan_range<F>(&mut self, from: u64, to: u64, mut visit: F) -> Result<()>
+    where
+        F: FnMut(Record) -> bool,
+    {
+        let next_offset = self.next_offset();
+        if from >= next_offset || from > to {
+            return Err(Error::new(
+                ErrorKind::NotFound,
+                format!("offset out of range: {}", from),
+            ));
+        }
+
+        let from = from.max(self.lowest_offset()?);
+        for offset in from..=to.min(next_offset - 1) {
+            match self.read_at_offset(offset) {
+                Ok(Some(record)) => {
+                    if !visit(record) {
+                        break;
+                    }
+                }
+                Ok(None) => return Err(LogError::Closed.into()),
+                Err(err)
+                    if matches!(err.kind(), ErrorKind::NotFound | ErrorKind::UnexpectedEof) => {}
+                Err(err) => return Err(err),
+            }
+        }
+        Ok(())
+    }
+
+    /// The offset just past the highest one the log has assigned.
+    pub fn next_offset(&self) -> u64 {
+        match self.segments.iter().flatten().last() {
+            Some(segment) => segment.next_offset,
+            None => self.config.initial_offset,
+        }
+    }
+
+    /// Compares the two logs record by record over every offset either one covers.
+    pub fn diff(&mut self, other: &mut Log) -> Result<Vec<OffsetDiff>> {
+        let (from, to) = match (self.offset_range(), other.offset_range()) {
+            (Some(a), Some(b)) => (a.0.min(b.0), a.1.max(b.1)),
+            (Some(range), None) | (None, Some(range)) => range,
+            (None, None) => return Ok(Vec::new()),
+        };
+
+        let mut diffs = Vec::new();
+        for offset in from..to {
+            match (self.record_at(offset)?, other.record_at(offset)?) {
+                (Some(a), Some(b)) if a.value != b.value => {
+                    diffs.push(OffsetDiff::ValueDiffers(offset))
+                }
+                (Some(_), None) => diffs.push(OffsetDiff::OnlyInSelf(offset)),
+                (None, Some(_)) => diffs.push(OffsetDiff::OnlyInOther(offset)),
+                _ => {}
+            }
+        }
+        Ok(diffs)
+    }
+
+    // the offsets `[lowest, next)` covered by the log's segments, if any
+    fn offset_range(&self) -> Option<(u64, u64)> {
+        let mut segments = self.segments.iter().flatten();
+        let lowest = segments.next()?.base_offset;
+        let next = self.segments.iter().flatten().last()?.next_offset;
+        (lowest < next).then_some((lowest, next))
+    }
+
+    // reads the record at `offset`, or `None` if the log has none there
+    fn record_at(&mut self, offset: u64) -> Result<Option<Record>> {
+        let segment = match self
+            .segments
+            .iter_mut()
+            .flatten()
+            .find(|seg| seg.base_offset <= offset && offset < seg.next_offset)
+        {
+            Some(segment) => segment,
+            None => return Ok(None),
+        };
+        match segment.read_at_offset(offset) {
+            Err(err) if err.kind() == ErrorKind::UnexpectedEof => Ok(None),
+            res => res,
+        }
+    }
+
+    /// Merges the adjacent sealed segments starting at `base_offsets` into one segment,
+    /// returning its base offset. Offsets don't change.
+    ///
+    /// The merged files are written and synced beside the originals, then renamed over the
+    /// first segment's, store first, before the others are removed. A crash at any point
+    /// leaves files that `setup` either discards or finishes merging.
+    pub fn merge_segments(&mut self, base_offsets: &[u64]) -> Result<u64> {
+        self.ensure_writable()?;
+        let invalid = |msg: String| Error::new(ErrorKind::InvalidInput, msg);
+        let first = match base_offsets.first() {
+            Some(&first) => first,
+            None => return Err(invalid("no segments to merge".to_string())),
+        };
+        let start = self
+            .segments
+            .iter()
+            .position(|seg| seg.as_ref().is_some_and(|seg| seg.base_offset == first))
+            .ok_or_else(|| invalid(format!("no segment at {}", first)))?;
+        let end = start + base_offsets.len();
+        if end > self.active_segment {
+            return Err(invalid("can not merge the active segment".to_string()));
+        }
+        for (segment, &base_offset) in self.segments[start..end].iter().zip(base_offsets) {
+            if segment.as_ref().map(|seg| seg.base_offset) != Some(base_offset) {
+                return Err(invalid(format!(
+                    "segment {} is not next to the ones before it",
+                    base_offset
+                )));
+            }
+        }
+
+        let store_tmp = self
+            .dir
+            .join(format!("{}.store.{}", first, segment::MERGE_EXTENSION));
+        let index_tmp = self
+            .dir
+            .join(format!("{}.index.{}", first, segment::MERGE_EXTENSION));
+        let mut merging: Vec<Segment> = self.segments.drain(start..end).flatten().collect();
+        if let Err(err) = Segment::write_merged(&mut merging, &store_tmp, &index_tmp, &self.config)
+        {
+            let _ = std::fs::remove_file(&store_tmp);
+            let _ = std::fs::remove_file(&index_tmp);
+            self.segments
+                .splice(start..start, merging.into_iter().map(Some));
+            return Err(err);
+        }
+
+        let bytes_read: u64 = merging.iter().map(Segment::size_bytes).sum();
+        for segment in merging.iter_mut() {
+            segment.close()?;
+        }
+        // the first segment's sidecar describes it before the merge
+        merging[0].discard_meta()?;
+        std::fs::rename(&store_tmp, self.dir.join(format!("{}.store", first)))?;
+        std::fs::rename(&index_tmp, self.dir.join(format!("{}.index", first)))?;
+        segment::sync_dir(&self.dir)?;
+        for segment in merging.iter_mut().skip(1) {
+            segment.remove()?;
+        }
+        // keep the inputs' write times, which retention and rolling go by
+        Segment::write_merged_meta(&merging)?;
+        segment::sync_dir(&self.dir)?;
+
+        let mut merged = Segment::new(&self.dir, first, self.config.clone())?;
+        merged.set_offset_assigner(self.assigner.clone());
+        self.compaction_stats.bytes_read += bytes_read;
+        self.compaction_stats.bytes_written += merged.size_bytes();
+        self.segments.insert(start, Some(merged));
+        self.active_segment -= merging.len() - 1;
+        self.reader_idx = self.reader_idx.min(start);
+        for base_offset in base_offsets {
+            self.segment_roots.remove(base_offset);
+        }
+        Ok(first)
+    }
+
+    pub fn compaction_stats(&self) -> CompactionStats {
+        self.compaction_stats
+    }
+
+    /// Returns how many records the mirror has failed to take under
+    /// `MirrorPolicy::Continue`, each one a gap in the mirror.
+    pub fn mirror_misses(&self) -> u64 {
+        self.mirror_misses
+    }
+
+    /// Returns the most recent record the mirror failed to take, if any.
+    pub fn last_mirror_miss(&self) -> Option<&MirrorMiss> {
+        self.last_mirror_miss.as_ref()
+    }
+
+    /// Computes a Merkle root over the records with offsets `<= up_to_offset`.
+    ///
+    /// Each segment's records hash into a segment root and the segment roots hash
+    /// into the log's root, so logs only compare equal when their segments line up.
+    /// Roots of fully covered sealed segments are cached.
+    pub fn merkle_root(&mut self, up_to_offset: u64) -> Result<Hash> {
+        let mut roots = Vec::new();
+        let active = self.active_segment;
+        for (idx, segment) in self.segments.iter_mut().enumerate() {
+            let segment = match segment {
+                Some(segment) if segment.base_offset <= up_to_offset => segment,
+                _ => continue,
+            };
+            if segment.next_offset == segment.base_offset {
+                continue;
+            }
+
+            let sealed = idx != active && segment.next_offset - 1 <= up_to_offset;
+            if let Some(root) = self
+                .segment_roots
+                .get(&segment.base_offset)
+                .filter(|_| sealed)
+            {
+                roots.push(*root);
+                continue;
+            }
+
+            if segment.is_closed() {
+                if !self.config.reopen_closed_segments {
+                    return Err(LogError::SegmentClosed {
+                        base_offset: segment.base_offset,
+                    }
+                    .into());
+                }
+                segment.reopen()?;
+            }
+            let root = merkle::root(&segment.record_hashes(up_to_offset)?);
+            if sealed {
+                self.segment_roots.insert(segment.base_offset, root);
+            }
+            roots.push(root);
+        }
+        Ok(merkle::root(&roots))
+    }
+
+    /// Writes every segment's buffered store bytes and dirty index pages back to its files.
+    pub fn flush(&mut self) -> Result<()> {
+        for segment in self.segments.iter_mut().flatten() {
+            segment.flush()?;
+        }
+        Ok(())
+    }
+
+    /// Flushes the log and opens every sealed segment's store and index, fixing a snapshot
+    /// of the records below the active segment's base offset.
+    ///
+    /// The handles go on reading the same bytes however the segments are merged or removed
+    /// after the call, where the platform lets open files be replaced.
+    pub fn snapshot_sealed(&mut self) -> Result<Vec<SegmentSnapshot>> {
+        self.flush()?;
+        self.segments[..self.active_segment]
+            .iter()
+            .flatten()
+            .map(|segment| {
+                Ok(SegmentSnapshot {
+                    base_offset: segment.base_offset,
+                    next_offset: segment.next_offset,
+                    store: segment.open_store_snapshot()?,
+                    index: segment.open_index_snapshot()?,
+                })
+            })
+            .collect()
+    }
+
+    /// Fsyncs every segment, so no record appended before the call is lost in a crash.
+    pub fn sync(&mut self) -> Result<()> {
+        for segment in self.segments.iter_mut().flatten() {
+            segment.sync()?;
+        }
+        Ok(())
+    }
+
+    /// Flushes every index with unflushed entries, returning how many were flushed.
+    pub fn flush_indexes(&mut self) -> Result<usize> {
+        let mut flushed = 0;
+        for segment in self.segments.iter_mut().flatten() {
+            if segment.flush_index()? {
+                flushed += 1;
+            }
+        }
+        Ok(flushed)
+    }
+
+    /// Seals the active segment if it has records and has gone `Config::seal_idle_after`
+    /// without an append, returning whether it was sealed.
+    pub fn seal_idle_segment(&mut self) -> Result<bool> {
+        let idle_after = match self.config.seal_idle_after {
+            Some(idle_after) => idle_after,
+            None => return Ok(false),
+        };
+        if self.last_append.elapsed() < idle_after {
+            return Ok(false);
+        }
+        self.roll_active_segment()
+    }
+
+    /// Rolls the active segment once its oldest record is `Config::max_segment_age` old,
+    /// returning whether it was rolled.
+    pub fn roll_aged_segment(&mut self) -> Result<bool> {
+        let max_age = match self.config.max_segment_age {
+            Some(max_age) => max_age,
+            None => return Ok(false),
+        };
+        let aged = match self.segments[self.active_segment] {
+            Some(ref segment) => segment
+                .active_since()
+                .is_some_and(|since| since.elapsed().is_ok_and(|age| age >= max_age)),
+            None => false,
+        };
+        if !aged {
+            return Ok(false);
+        }
+        self.roll_active_segment()
+    }
+
+    // moves appends onto a new segment, unless the active one is still empty
+    fn roll_active_segment(&mut self) -> Result<bool> {
+        if self.config.read_only {
+            return Ok(false);
+        }
+        let next_offset = match self.segments[self.active_segment] {
+            Some(ref mut segment)
+                if !segment.is_closed() && segment.next_offset > segment.base_offset =>
+            {
+                segment.flush()?;
+                segment.next_offset
+            }
+            _ => return Ok(false),
+        };
+        self.new_segment(next_offset)?;
+        Ok(true)
+    }
+
+    /// Starts a thread running the log's periodic maintenance: flushing indexes every
+    /// `Config::index_flush_interval`, which bounds how many index entries a crash can lose,
+    /// rolling the active segment after `Config::seal_idle_after` or
+    /// `Config::max_segment_age`, and enforcing retention every `Config::retention_interval`.
+    /// The thread exits once the log is closed or dropped, noticing it on the next tick or
+    /// as soon as it's unparked.
+    pub fn spawn_background_tasks(log: &Arc<RwLock<Log>>) -> Option<JoinHandle<()>> {
+        let config = log.read().ok()?.config.clone();
+        let tick = [
+            config.index_flush_interval,
+            config.seal_idle_after,
+            config.max_segment_age,
+            config.retention_interval,
+        ]
+        .into_iter()
+        .flatten()
+        .min()?
+        .max(Duration::from_millis(1));
+        let log = Arc::downgrade(log);
+        let handle = std::thread::spawn(move || {
+            let mut last_flush = Instant::now();
+            let mut last_retention = Instant::now();
+            loop {
+                std::thread::park_timeout(tick);
+                let log = match log.upgrade() {
+                    Some(log) => log,
+                    None => return,
+                };
+                let mut log = match log.write() {
+                    Ok(log) => log,
+                    Err(_) => return,
+                };
+                if log.is_closed() {
+                    return;
+                }
+                // failures are retried on the next tick
+                if let Some(interval) = config.index_flush_interval {
+                    if last_flush.elapsed() >= interval {
+                        let _ = log.flush_indexes();
+                        last_flush = Instant::now();
+                    }
+                }
+                let _ = log.seal_idle_segment();
+                let _ = log.roll_aged_segment();
+                if let Some(interval) = config.retention_interval {
+                    if last_retention.elapsed() >= interval {
+                        let _ = log.enforce_retention();
+                        last_retention = Instant::now();
+                    }
+                }
             }
+        });
+        Some(handle)
+    }
+
+    /// Returns whether the log has been closed, which stops it rolling new segments.
+    pub fn is_closed(&self) -> bool {
+        self.segments[self.active_segment]
+            .as_ref()
+            .is_none_or(Segment::is_closed)
+    }
+
+    pub fn close(&mut self) -> Result<()> {
+        for segment in self.segments.iter_mut().flatten() {
+            segment.close()?;
+        }
+        if let Some(ref mut mirror) = self.mirror {
+            mirror.close()?;
         }
 
         self.reader_idx = self.segments.len();
@@ -89,12 +802,19 @@ impl Log {
     }
 
     pub fn remove(&mut self) -> Result<()> {
+        self.ensure_writable()?;
         self.close()?;
+        if let Some(ref mut mirror) = self.mirror {
+            mirror.remove()?;
+        }
         std::fs::remove_dir_all(&self.dir)
     }
 
     pub fn reset(&mut self) -> Result<()> {
         self.remove()?;
+        if let Some(ref mut mirror) = self.mirror {
+            mirror.setup()?;
+        }
         self.setup()
     }
 
@@ -102,26 +822,38 @@ impl Log {
         if let Some(ref segment) = self.segments[0] {
             return Ok(segment.base_offset);
         }
-        return Err(Error::new(ErrorKind::Other, "corrupted log"));
+        Err(Error::other("corrupted log"))
     }
 
-    #[inline]
     pub fn highest_offset(&self) -> Result<u64> {
-        if let Some(ref segment) = self.segments.last().unwrap() {
-            let offset = segment.next_offset;
-            if offset == 0 {
-                return Ok(0);
-            }
-            return Ok(offset - 1);
+        // the empty segment a roll or advance_to leaves active holds nothing yet
+        let highest = self
+            .segments
+            .iter()
+            .flatten()
+            .filter(|segment| segment.next_offset > segment.base_offset)
+            .map(|segment| segment.next_offset - 1)
+            .max();
+        if let Some(offset) = highest {
+            return Ok(offset);
+        }
+        if let Some(Some(ref segment)) = self.segments.last() {
+            return Ok(segment.next_offset.saturating_sub(1));
         }
-        return Err(Error::new(ErrorKind::Other, "corrupted log"));
+        Err(Error::other("corrupted log"))
     }
 
+    /// Removes the segments whose records are all below `lowest`. Segments are only
+    /// removed whole, so records below `lowest` sharing a segment with ones at or above it
+    /// stay readable. The active segment is always kept.
     pub fn truncate(&mut self, lowest: u64) -> Result<()> {
+        self.ensure_writable()?;
+        let active = self.active_segment;
         let mut segments: Vec<Option<Segment>> = Vec::new();
-        for s in self.segments.iter_mut() {
+        for (idx, s) in self.segments.iter_mut().enumerate() {
             if let Some(mut segment) = s.take() {
-                if segment.next_offset <= lowest + 1 {
+                if idx != active && segment.next_offset <= lowest {
+                    self.compaction_stats.bytes_reclaimed += segment.size_bytes();
                     segment.remove()?;
                     continue;
                 }
@@ -131,60 +863,398 @@ impl Log {
         }
         self.reader_idx = 0;
         self.segments = segments;
+        self.active_segment = self.segments.len() - 1;
+        self.segment_roots.clear();
+        if let Some(ref mut mirror) = self.mirror {
+            mirror.truncate(lowest)?;
+        }
+        Ok(())
+    }
+
+    /// Removes whole segments, oldest first, while the stores total more than
+    /// `Config::retention_max_bytes` or the oldest segment's newest record is older than
+    /// `Config::retention_max_age`, returning how many were removed. The active segment
+    /// is kept however big or old it is.
+    pub fn enforce_retention(&mut self) -> Result<usize> {
+        let (max_bytes, max_age) = (
+            self.config.retention_max_bytes,
+            self.config.retention_max_age,
+        );
+        if max_bytes.is_none() && max_age.is_none() {
+            return Ok(0);
+        }
+        self.ensure_writable()?;
+        let cutoff = max_age.and_then(|max_age| SystemTime::now().checked_sub(max_age));
+        let mut total: u64 = self
+            .segments
+            .iter()
+            .flatten()
+            .map(Segment::store_bytes)
+            .sum();
+        let mut removed = 0;
+        let mut result = Ok(());
+        while removed < self.active_segment {
+            let stale = match (&self.segments[removed], cutoff) {
+                (Some(segment), Some(cutoff)) => segment.last_write().is_some_and(|at| at < cutoff),
+                _ => false,
+            };
+            if !stale && max_bytes.is_none_or(|max_bytes| total <= max_bytes) {
+                break;
+            }
+            if let Some(mut segment) = self.segments[removed].take() {
+                total -= segment.store_bytes();
+                self.compaction_stats.bytes_reclaimed += segment.size_bytes();
+                self.segment_roots.remove(&segment.base_offset);
+                result = segment.remove();
+            }
+            removed += 1;
+            if result.is_err() {
+                break;
+            }
+        }
+        // drop the slots taken so far even when a removal failed
+        self.segments.drain(..removed);
+        self.active_segment -= removed;
+        self.reader_idx = self.reader_idx.saturating_sub(removed);
+        result?;
+        if let Some(ref mut mirror) = self.mirror {
+            mirror.enforce_retention()?;
+        }
+        Ok(removed)
+    }
+
+    /// Returns a cursor over the store bytes of every segment, like the log's own `Read`
+    /// impl but independent of it and of other readers. It sees what had been flushed
+    /// when it was created.
+    pub fn reader(&self) -> Result<LogReader> {
+        let segments = self
+            .segments
+            .iter()
+            .flatten()
+            .map(Segment::open_store_snapshot)
+            .collect::<Result<Vec<_>>>()?;
+        Ok(LogReader::new(segments))
+    }
+
+    /// Returns a [`LogReader`] that starts at the record with `offset`, or the next one
+    /// after it when `offset` falls in a gap. Pending writes are flushed first so the
+    /// reader sees every record appended so far.
+    pub fn reader_from(&mut self, offset: u64) -> Result<LogReader> {
+        if offset < self.lowest_offset()? || offset > self.highest_offset()? {
+            return Err(Error::new(
+                ErrorKind::NotFound,
+                format!("offset out of range: {}", offset),
+            ));
+        }
+        self.flush()?;
+
+        let mut segments = Vec::new();
+        for segment in self.segments.iter_mut().flatten() {
+            if segment.next_offset <= offset {
+                continue;
+            }
+            if !segments.is_empty() || segment.base_offset > offset {
+                segments.push(segment.open_store_snapshot()?);
+                continue;
+            }
+            if segment.is_closed() {
+                if !self.config.reopen_closed_segments {
+                    return Err(LogError::SegmentClosed {
+                        base_offset: segment.base_offset,
+                    }
+                    .into());
+                }
+                segment.reopen()?;
+            }
+            let pos = segment.position_of(offset)?;
+            let (mut file, len) = segment.open_store_snapshot()?;
+            file.seek(SeekFrom::Start(pos))?;
+            segments.push((file, len - pos));
+        }
+        Ok(LogReader::new(segments))
+    }
+
+    /// Moves the log forward so the next append gets `offset`, leaving a gap behind it.
+    pub fn advance_to(&mut self, offset: u64) -> Result<()> {
+        self.ensure_writable()?;
+        let idx = self.active_segment;
+        let (base_offset, next_offset) = match self.segments[idx] {
+            Some(ref segment) => (segment.base_offset, segment.next_offset),
+            None => return Err(Error::other("corrupted log")),
+        };
+
+        if offset < next_offset {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!(
+                    "can not advance backward from {} to {}",
+                    next_offset, offset
+                ),
+            ));
+        }
+        if offset == next_offset && base_offset == next_offset {
+            return Ok(());
+        }
+
+        // an empty active segment has nothing worth sealing
+        if base_offset == next_offset {
+            if let Some(mut segment) = self.segments.remove(idx) {
+                segment.remove()?;
+            }
+        }
+        self.new_segment(offset)?;
+        if let Some(ref mut mirror) = self.mirror {
+            mirror.advance_to(offset)?;
+        }
         Ok(())
     }
 
     fn setup(&mut self) -> Result<()> {
         let files = std::fs::read_dir(&self.dir)?;
-        let mut base_offsets: Vec<u64> = Vec::new();
+        let mut stores = BTreeSet::new();
+        let mut indexes = BTreeSet::new();
+        let mut merge_files = Vec::new();
         for file in files.into_iter() {
             let file = file?;
             let path = file.path();
+            if file.file_name() == BYTE_ORDER_FILE
+                || file.file_name() == TRANSFORMS_FILE
+                || path
+                    .extension()
+                    .is_some_and(|ext| ext == segment::META_EXTENSION)
+            {
+                continue;
+            }
+            if path
+                .extension()
+                .is_some_and(|ext| ext == segment::MERGE_EXTENSION)
+            {
+                merge_files.push(path);
+                continue;
+            }
             let off_str = path
                 .file_stem()
-                .ok_or(Error::new(ErrorKind::Other, "can not get the file stem"))?;
+                .ok_or(Error::other("can not get the file stem"))?;
             let offset = off_str
                 .to_str()
-                .ok_or(Error::new(ErrorKind::Other, "can convert OsString to str"))?
+                .ok_or(Error::other("can convert OsString to str"))?
                 .parse::<u64>()
-                .map_err(|e| Error::new(ErrorKind::Other, e.to_string()))?;
-            base_offsets.push(offset);
+                .map_err(|e| Error::other(e.to_string()))?;
+            let files = match path.extension().and_then(|ext| ext.to_str()) {
+                Some("store") => &mut stores,
+                Some("index") => &mut indexes,
+                _ => {
+                    return Err(Error::new(
+                        ErrorKind::InvalidData,
+                        format!("unexpected file {} in the log directory", path.display()),
+                    ))
+                }
+            };
+            files.insert(offset);
+        }
+        if let Some(&offset) = stores.symmetric_difference(&indexes).next() {
+            let (found, missing) = if stores.contains(&offset) {
+                ("store", "index")
+            } else {
+                ("index", "store")
+            };
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!(
+                    "segment {} has a {} file but no {} file",
+                    offset, found, missing
+                ),
+            ));
+        }
+
+        if !self.config.read_only {
+            self.recover_merge(&merge_files)?;
         }
 
-        base_offsets.sort_unstable();
-        for offset in base_offsets.iter().step_by(2) {
-            self.new_segment(*offset)?;
+        for offset in stores {
+            self.new_segment(offset)?;
+        }
+        if !self.config.read_only {
+            self.remove_merged_segments()?;
         }
 
         if self.segments.is_empty() {
+            if self.config.read_only {
+                return Err(Error::new(
+                    ErrorKind::NotFound,
+                    "no segments in read-only log",
+                ));
+            }
             self.new_segment(self.config.initial_offset)?;
         }
 
         Ok(())
     }
 
-    fn new_segment(&mut self, offset: u64) -> Result<()> {
-        let s = Segment::new(&self.dir, offset, self.config.clone())?;
-        self.segments.push(Some(s));
-        self.active_segment = self.segments.len() - 1;
+    // a merge that crashed before renaming its store is discarded; one that renamed its
+    // store but not its index gets its index renamed too
+    fn recover_merge(&self, merge_files: &[PathBuf]) -> Result<()> {
+        for path in merge_files {
+            let target = path.with_extension("");
+            let store_tmp = target.with_extension(format!("store.{}", segment::MERGE_EXTENSION));
+            let is_index = target.extension().is_some_and(|ext| ext == "index");
+            if is_index && !merge_files.contains(&store_tmp) {
+                std::fs::rename(path, &target)?;
+                // a sidecar left from before the merge has the first segment's old offsets
+                let meta = target.with_extension(segment::META_EXTENSION);
+                if meta.exists() {
+                    std::fs::remove_file(meta)?;
+                }
+            } else {
+                std::fs::remove_file(path)?;
+            }
+        }
+        if !merge_files.is_empty() {
+            segment::sync_dir(&self.dir)?;
+        }
         Ok(())
     }
-}
 
-impl Read for Log {
-    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
-        loop {
-            if self.reader_idx >= self.segments.len() {
-                return Ok(0);
+    // removes the segments a merged segment covers, left behind by a merge that crashed
+    // before removing them
+    fn remove_merged_segments(&mut self) -> Result<()> {
+        let mut idx = 1;
+        while idx < self.segments.len() {
+            let covered = match (&self.segments[idx - 1], &self.segments[idx]) {
+                (Some(prev), Some(seg)) => seg.base_offset < prev.next_offset,
+                _ => false,
+            };
+            if !covered {
+                idx += 1;
+                continue;
             }
-            match self.segments[self.reader_idx] {
-                None => {
-                    self.reader_idx += 1;
-                    continue;
-                }
-
-                Some(ref mut segment) => match segment.read(buf) {
-                    Ok(n) => {
+            if let Some(mut segment) = self.segments.remove(idx) {
+                segment.remove()?;
+            }
+        }
+        self.active_segment = self.segments.len().saturating_sub(1);
+        Ok(())
+    }
+
+    fn check_dir(&self) -> Result<()> {
+        match std::fs::metadata(&self.dir) {
+            Ok(meta) if meta.is_dir() => Ok(()),
+            Ok(_) => Err(Error::new(
+                ErrorKind::NotADirectory,
+                format!("log path {} is not a directory", self.dir.display()),
+            )),
+            Err(err) if err.kind() == ErrorKind::NotFound => {
+                if !self.config.create_dir || self.config.read_only {
+                    return Err(Error::new(
+                        ErrorKind::NotFound,
+                        format!("log directory {} does not exist", self.dir.display()),
+                    ));
+                }
+                std::fs::create_dir_all(&self.dir)
+            }
+            Err(err) => Err(err),
+        }
+    }
+
+    // fails if the log was written in another byte order than the configured one, and
+    // records the configured one in a log that doesn't say yet
+    fn check_byte_order(&self) -> Result<()> {
+        let path = self.dir.join(BYTE_ORDER_FILE);
+        let recorded = match std::fs::read_to_string(&path) {
+            Ok(recorded) => Some(
+                recorded
+                    .trim()
+                    .parse::<ByteOrderKind>()
+                    .map_err(|e| Error::new(ErrorKind::InvalidData, e))?,
+            ),
+            Err(err) if err.kind() == ErrorKind::NotFound => None,
+            Err(err) => return Err(err),
+        };
+        let written = match recorded {
+            Some(order) => order,
+            // segments written before the byte order was recorded are big-endian
+            None if std::fs::read_dir(&self.dir)?.next().is_some() => ByteOrderKind::BigEndian,
+            None => self.config.byte_order,
+        };
+        if written != self.config.byte_order {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!("log is written {}, not {}", written, self.config.byte_order),
+            ));
+        }
+        if recorded.is_none() && !self.config.read_only {
+            std::fs::write(&path, format!("{}\n", written))?;
+        }
+        Ok(())
+    }
+
+    // fails if the log was written through another transform pipeline than the configured
+    // one, and records the configured one in a log that doesn't say yet
+    fn check_transforms(&self) -> Result<()> {
+        let path = self.dir.join(TRANSFORMS_FILE);
+        let configured: Vec<&str> = self.config.transforms.iter().map(|t| t.name()).collect();
+        match std::fs::read_to_string(&path) {
+            Ok(recorded) => {
+                let recorded: Vec<&str> = recorded.lines().collect();
+                if recorded != configured {
+                    return Err(Error::new(
+                        ErrorKind::InvalidData,
+                        format!(
+                            "log is written with transforms [{}], not [{}]",
+                            recorded.join(", "),
+                            configured.join(", ")
+                        ),
+                    ));
+                }
+                Ok(())
+            }
+            Err(err) if err.kind() == ErrorKind::NotFound => {
+                if !self.config.read_only {
+                    let names: String = configured
+                        .iter()
+                        .map(|name| format!("{}\n", name))
+                        .collect();
+                    std::fs::write(&path, names)?;
+                }
+                Ok(())
+            }
+            Err(err) => Err(err),
+        }
+    }
+
+    fn ensure_writable(&self) -> Result<()> {
+        if self.config.read_only {
+            return Err(Error::new(ErrorKind::PermissionDenied, "log is read-only"));
+        }
+        Ok(())
+    }
+
+    fn new_segment(&mut self, offset: u64) -> Result<()> {
+        let mut s = Segment::new(&self.dir, offset, self.config.clone())?;
+        if self.config.sync_dir && !self.config.read_only {
+            segment::sync_dir(&self.dir)?;
+        }
+        s.set_offset_assigner(self.assigner.clone());
+        self.segments.push(Some(s));
+        self.active_segment = self.segments.len() - 1;
+        Ok(())
+    }
+}
+
+impl Read for Log {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        loop {
+            if self.reader_idx >= self.segments.len() {
+                return Ok(0);
+            }
+            match self.segments[self.reader_idx] {
+                None => {
+                    self.reader_idx += 1;
+                    continue;
+                }
+
+                Some(ref mut segment) => match segment.read(buf) {
+                    Ok(n) => {
                         return Ok(n);
                     }
                     Err(e) => {
@@ -204,18 +1274,28 @@ impl Read for Log {
 mod tests {
     use prost::Message;
 
-    use crate::log::store::LEN_WIDTH;
+    use byteorder::ByteOrder;
+
+    use crate::log::{
+        index::{ENTWIDTH, HDRWIDTH, OFFWIDTH},
+        store::HEADER_WIDTH,
+    };
 
     use super::*;
 
     #[test]
     fn test_log() {
-        let tests: Vec<(&str, fn(Log))> = vec![
+        type Scenario = (&'static str, fn(Log));
+        let tests: Vec<Scenario> = vec![
             ("append and read a record succeeds", test_append_read),
             ("offset out of range error", test_out_of_range_err),
             ("init with existing segments", test_init_existing),
             ("reader", test_reader),
             ("truncate", test_truncate),
+            ("open read-only", test_open_read_only),
+            ("advance to", test_advance_to),
+            ("offset assigner", test_offset_assigner),
+            ("sync", test_sync),
         ];
 
         for (scen, func) in tests {
@@ -226,8 +1306,10 @@ mod tests {
                 .tempdir()
                 .unwrap();
 
-            let mut c = Config::default();
-            c.max_store_bytes = 32;
+            let c = Config {
+                max_store_bytes: 32,
+                ..Default::default()
+            };
             let log = Log::new(&dir, c).unwrap();
             func(log);
         }
@@ -238,10 +1320,18 @@ mod tests {
             value: b"hello world".into(),
             offset: 0,
         };
-        let offset = log.append(append.clone()).unwrap().unwrap();
+        let offset = log.append(append.clone()).unwrap();
         assert_eq!(0u64, offset);
         let read = log.read_at_offset(offset).unwrap().unwrap();
         assert_eq!(append.value, read.value);
+
+        // appending to a closed log fails rather than quietly doing nothing
+        log.close().unwrap();
+        let err = log.append(append).unwrap_err();
+        assert!(matches!(
+            err.get_ref().and_then(|e| e.downcast_ref::<LogError>()),
+            Some(LogError::Closed)
+        ));
     }
     fn test_out_of_range_err(mut log: Log) {
         let read = log.read_at_offset(1);
@@ -254,7 +1344,7 @@ mod tests {
         };
 
         for _ in 0..3 {
-            log.append(append.clone()).unwrap().unwrap();
+            log.append(append.clone()).unwrap();
         }
 
         log.close().unwrap();
@@ -276,11 +1366,11 @@ mod tests {
         };
 
         let offset = log.append(append.clone()).unwrap();
-        assert_eq!(0u64, offset.unwrap());
+        assert_eq!(0u64, offset);
 
         let mut buf: Vec<u8> = Vec::new();
         log.read_to_end(&mut buf).unwrap();
-        let start = LEN_WIDTH as usize;
+        let start = HEADER_WIDTH as usize;
         let end = start + append.value.len() + 2;
         let read: Record = Message::decode(&buf[start..end]).unwrap();
         assert_eq!(append.value, read.value);
@@ -292,11 +1382,1651 @@ mod tests {
         };
 
         for _ in 0..3u64 {
-            log.append(append.clone()).unwrap().unwrap();
+            log.append(append.clone()).unwrap();
         }
 
+        // offsets 0 and 1 share the first segment, so truncating between them keeps both
+        assert_eq!(2, log.segments[1].as_ref().unwrap().base_offset);
         log.truncate(1).unwrap();
+        assert_eq!(2, log.segments.len());
+        assert_eq!(0, log.read_at_offset(0).unwrap().unwrap().offset);
+        assert_eq!(1, log.read_at_offset(1).unwrap().unwrap().offset);
 
+        // truncating at the second segment's base drops the first
+        log.truncate(2).unwrap();
         assert!(log.read_at_offset(0).is_err());
+        assert!(log.read_at_offset(1).is_err());
+        assert_eq!(2, log.read_at_offset(2).unwrap().unwrap().offset);
+        assert_eq!(0, log.active_segment);
+
+        // the active segment is kept past its last record, and appends still land in it
+        log.truncate(100).unwrap();
+        assert_eq!(1, log.segments.len());
+        assert_eq!(3, log.append(append).unwrap());
+        assert_eq!(3, log.read_at_offset(3).unwrap().unwrap().offset);
+    }
+
+    fn test_open_read_only(mut log: Log) {
+        let append = Record {
+            value: b"hello world".into(),
+            offset: 0,
+        };
+
+        for _ in 0..3u64 {
+            log.append(append.clone()).unwrap();
+        }
+        log.close().unwrap();
+
+        let sizes = |dir: &Path| {
+            let mut sizes: Vec<(PathBuf, u64)> = std::fs::read_dir(dir)
+                .unwrap()
+                .map(|e| {
+                    let e = e.unwrap();
+                    (e.path(), e.metadata().unwrap().len())
+                })
+                .collect();
+            sizes.sort();
+            sizes
+        };
+        let before = sizes(&log.dir);
+        for (path, _) in before.iter() {
+            let mut perms = std::fs::metadata(path).unwrap().permissions();
+            perms.set_readonly(true);
+            std::fs::set_permissions(path, perms).unwrap();
+        }
+
+        let mut ro = Log::open_read_only(&log.dir, log.config.clone()).unwrap();
+        assert_eq!(0, ro.lowest_offset().unwrap());
+        assert_eq!(2, ro.highest_offset().unwrap());
+        for offset in 0..3u64 {
+            let read = ro.read_at_offset(offset).unwrap().unwrap();
+            assert_eq!(append.value, read.value);
+        }
+
+        let mut buf: Vec<u8> = Vec::new();
+        ro.read_to_end(&mut buf).unwrap();
+        assert!(!buf.is_empty());
+
+        let err = ro.append(append.clone()).map_err(|e| e.kind());
+        assert_eq!(err, Err(ErrorKind::PermissionDenied));
+        let err = ro.truncate(1).map_err(|e| e.kind());
+        assert_eq!(err, Err(ErrorKind::PermissionDenied));
+        ro.close().unwrap();
+
+        // nothing was grown, trimmed or created
+        assert_eq!(before, sizes(&log.dir));
+
+        let empty = tempfile::Builder::new()
+            .prefix("read-only-empty-test")
+            .tempdir()
+            .unwrap();
+        let err = Log::open_read_only(&empty, log.config)
+            .map(|_| ())
+            .map_err(|e| e.kind());
+        assert_eq!(err, Err(ErrorKind::NotFound));
+    }
+
+    fn test_advance_to(mut log: Log) {
+        let append = Record {
+            value: b"hello world".into(),
+            offset: 0,
+        };
+
+        for _ in 0..3u64 {
+            log.append(append.clone()).unwrap();
+        }
+
+        log.advance_to(100).unwrap();
+        assert_eq!(2, log.highest_offset().unwrap());
+        let offset = log.append(append.clone()).unwrap();
+        assert_eq!(100, offset);
+        assert_eq!(100, log.read_at_offset(100).unwrap().unwrap().offset);
+        assert_eq!(2, log.read_at_offset(2).unwrap().unwrap().offset);
+        assert!(log.read_at_offset(3).is_err());
+        assert!(log.read_at_offset(99).is_err());
+
+        let err = log.advance_to(50).map_err(|e| e.kind());
+        assert_eq!(err, Err(ErrorKind::InvalidInput));
+
+        // advancing past an empty active segment replaces it
+        log.advance_to(200).unwrap();
+        log.advance_to(300).unwrap();
+        assert_eq!(300, log.append(append.clone()).unwrap());
+
+        log.close().unwrap();
+        let mut log = Log::new(log.dir, log.config).unwrap();
+        assert_eq!(0, log.lowest_offset().unwrap());
+        assert_eq!(100, log.read_at_offset(100).unwrap().unwrap().offset);
+        assert_eq!(301, log.append(append).unwrap());
+    }
+
+    #[derive(Debug)]
+    struct Stride(u64);
+
+    impl OffsetAssigner for Stride {
+        fn assign(&self, next_offset: u64) -> u64 {
+            next_offset.div_ceil(self.0) * self.0
+        }
+    }
+
+    fn test_offset_assigner(mut log: Log) {
+        let append = Record {
+            value: b"hello world".into(),
+            offset: 0,
+        };
+
+        log.set_offset_assigner(Arc::new(Stride(2)));
+        for i in 0..6u64 {
+            let offset = log.append(append.clone()).unwrap();
+            assert_eq!(i * 2, offset);
+        }
+
+        for i in 0..6u64 {
+            let read = log.read_at_offset(i * 2).unwrap().unwrap();
+            assert_eq!(i * 2, read.offset);
+            assert_eq!(append.value, read.value);
+            assert!(log.read_at_offset(i * 2 + 1).is_err());
+        }
+        assert_eq!(10, log.highest_offset().unwrap());
+
+        log.close().unwrap();
+        let mut log = Log::new(log.dir, log.config).unwrap();
+        log.set_offset_assigner(Arc::new(Stride(2)));
+        assert_eq!(10, log.read_at_offset(10).unwrap().unwrap().offset);
+        assert_eq!(12, log.append(append).unwrap());
+    }
+
+    fn test_sync(mut log: Log) {
+        let append = Record {
+            value: b"hello world".into(),
+            offset: 0,
+        };
+        for _ in 0..5 {
+            log.append(append.clone()).unwrap();
+        }
+        let on_disk = |log: &Log| -> u64 {
+            std::fs::read_dir(&log.dir)
+                .unwrap()
+                .map(|entry| entry.unwrap().path())
+                .filter(|path| path.extension().is_some_and(|ext| ext == "store"))
+                .map(|path| std::fs::metadata(path).unwrap().len())
+                .sum()
+        };
+        let appended: u64 = (0..5)
+            .map(|offset| {
+                let record = Record {
+                    offset,
+                    ..append.clone()
+                };
+                HEADER_WIDTH + record.encoded_len() as u64
+            })
+            .sum();
+        assert!(on_disk(&log) < appended);
+
+        log.sync().unwrap();
+        assert_eq!(appended, on_disk(&log));
+    }
+
+    #[test]
+    fn test_index_flusher() {
+        let dir = tempfile::Builder::new()
+            .prefix("index-flusher-test")
+            .tempdir()
+            .unwrap();
+        let c = Config {
+            max_store_bytes: 1024,
+            index_flush_interval: Some(Duration::from_millis(10)),
+            ..Default::default()
+        };
+        let log = Arc::new(RwLock::new(Log::new(&dir, c).unwrap()));
+        let flusher = Log::spawn_background_tasks(&log).unwrap();
+
+        let append = Record {
+            value: b"hello world".into(),
+            offset: 0,
+        };
+        for _ in 0..3 {
+            log.write().unwrap().append(append.clone()).unwrap();
+        }
+        std::thread::sleep(Duration::from_millis(100));
+        // the flusher already wrote the entries back, so nothing is left dirty
+        assert_eq!(0, log.write().unwrap().flush_indexes().unwrap());
+
+        // crash: drop the log without closing it
+        drop(log);
+        flusher.join().unwrap();
+
+        let index = std::fs::read(dir.path().join("0.index")).unwrap();
+        for i in 0..3usize {
+            let entry = &index[HDRWIDTH + i * ENTWIDTH..HDRWIDTH + (i + 1) * ENTWIDTH];
+            assert_eq!(i as u32, byteorder::BigEndian::read_u32(&entry[..OFFWIDTH]));
+        }
+
+        let log = Arc::new(RwLock::new(Log::new(&dir, Config::default()).unwrap()));
+        assert!(Log::spawn_background_tasks(&log).is_none());
+    }
+
+    #[test]
+    fn test_seal_idle_segment() {
+        let dir = tempfile::Builder::new()
+            .prefix("seal-idle-test")
+            .tempdir()
+            .unwrap();
+        let c = Config {
+            max_store_bytes: 1024,
+            seal_idle_after: Some(Duration::from_millis(20)),
+            ..Default::default()
+        };
+        let append = Record {
+            value: b"hello world".into(),
+            offset: 0,
+        };
+
+        let mut log = Log::new(&dir, c).unwrap();
+        // an empty active segment is never sealed
+        std::thread::sleep(Duration::from_millis(30));
+        assert!(!log.seal_idle_segment().unwrap());
+
+        log.append(append.clone()).unwrap();
+        assert!(!log.seal_idle_segment().unwrap());
+        std::thread::sleep(Duration::from_millis(30));
+        assert!(log.seal_idle_segment().unwrap());
+        assert_eq!(2, log.segments.len());
+        assert!(!log.seal_idle_segment().unwrap());
+
+        let log = Arc::new(RwLock::new(log));
+        let tasks = Log::spawn_background_tasks(&log).unwrap();
+        assert_eq!(1, log.write().unwrap().append(append.clone()).unwrap());
+        std::thread::sleep(Duration::from_millis(100));
+        {
+            let mut log = log.write().unwrap();
+            assert_eq!(3, log.segments.len());
+            assert_eq!(0, log.read_at_offset(0).unwrap().unwrap().offset);
+            assert_eq!(1, log.read_at_offset(1).unwrap().unwrap().offset);
+            assert_eq!(2, log.append(append.clone()).unwrap());
+        }
+        drop(log);
+        tasks.join().unwrap();
+
+        // closing the log ends the tasks without rolling onto a new segment
+        let dir = tempfile::Builder::new()
+            .prefix("seal-idle-close-test")
+            .tempdir()
+            .unwrap();
+        let c = Config {
+            max_store_bytes: 1024,
+            seal_idle_after: Some(Duration::from_millis(20)),
+            ..Default::default()
+        };
+        let mut log = Log::new(&dir, c).unwrap();
+        log.append(append).unwrap();
+        let log = Arc::new(RwLock::new(log));
+        let tasks = Log::spawn_background_tasks(&log).unwrap();
+        log.write().unwrap().close().unwrap();
+        assert!(log.read().unwrap().is_closed());
+        std::thread::sleep(Duration::from_millis(60));
+        tasks.join().unwrap();
+        let stores = std::fs::read_dir(&dir)
+            .unwrap()
+            .filter(|entry| {
+                let path = entry.as_ref().unwrap().path();
+                path.extension().is_some_and(|ext| ext == "store")
+            })
+            .count();
+        assert_eq!(1, stores);
+        assert!(!log.write().unwrap().seal_idle_segment().unwrap());
+    }
+
+    #[test]
+    fn test_mirror() {
+        let dir = tempfile::Builder::new()
+            .prefix("mirror-primary-test")
+            .tempdir()
+            .unwrap();
+        let mirror_dir = tempfile::Builder::new()
+            .prefix("mirror-secondary-test")
+            .tempdir()
+            .unwrap();
+        let c = Config {
+            max_store_bytes: 32,
+            mirror_dir: Some(mirror_dir.path().join("standby")),
+            ..Default::default()
+        };
+        let append = |i: u64| Record {
+            value: format!("record {}", i).into_bytes(),
+            offset: 0,
+        };
+
+        let mut log = Log::new(&dir, c.clone()).unwrap();
+        for i in 0..5 {
+            log.append(append(i)).unwrap();
+        }
+        log.advance_to(10).unwrap();
+        log.append(append(10)).unwrap();
+        log.close().unwrap();
+
+        let mut primary = Log::new(&dir, Config::default()).unwrap();
+        let mut mirror = Log::new(c.mirror_dir.unwrap(), Config::default()).unwrap();
+        assert_eq!(
+            primary.lowest_offset().unwrap(),
+            mirror.lowest_offset().unwrap()
+        );
+        assert_eq!(10, mirror.highest_offset().unwrap());
+        for offset in (0..5).chain(10..11) {
+            let want = primary.read_at_offset(offset).unwrap().unwrap();
+            let got = mirror.read_at_offset(offset).unwrap().unwrap();
+            assert_eq!(want, got);
+        }
+        assert!(mirror.read_at_offset(5).is_err());
+    }
+
+    #[test]
+    fn test_mirror_policy() {
+        let dir = tempfile::Builder::new()
+            .prefix("mirror-policy-test")
+            .tempdir()
+            .unwrap();
+        let c = Config {
+            mirror_dir: Some(dir.path().join("mirror")),
+            ..Default::default()
+        };
+        let primary_dir = dir.path().join("primary");
+        std::fs::create_dir(&primary_dir).unwrap();
+        let append = Record {
+            value: b"hello world".into(),
+            offset: 0,
+        };
+
+        let mut log = Log::new(&primary_dir, c).unwrap();
+        log.append(append.clone()).unwrap();
+        assert_eq!(0, log.mirror_misses());
+        // lose the mirror's disk
+        log.mirror.as_mut().unwrap().close().unwrap();
+        std::fs::remove_dir_all(dir.path().join("mirror")).unwrap();
+
+        assert_eq!(1, log.append(append.clone()).unwrap());
+        assert_eq!(1, log.mirror_misses());
+        assert_eq!(1, log.last_mirror_miss().unwrap().offset);
+        log.config.mirror_policy = MirrorPolicy::Fail;
+        assert!(log.append(append.clone()).is_err());
+        assert_eq!(1, log.mirror_misses());
+        // the primary still took the record
+        assert_eq!(2, log.read_at_offset(2).unwrap().unwrap().offset);
+    }
+
+    #[test]
+    fn test_merkle_root() {
+        let records: Vec<Record> = (0..7)
+            .map(|i| Record {
+                value: format!("record {}", i).into_bytes(),
+                offset: 0,
+            })
+            .collect();
+        let c = Config {
+            max_store_bytes: 32,
+            max_index_bytes: 1024,
+            ..Default::default()
+        };
+        let new_log = |records: &[Record]| {
+            let dir = tempfile::Builder::new()
+                .prefix("merkle-test")
+                .tempdir()
+                .unwrap();
+            let mut log = Log::new(&dir, c.clone()).unwrap();
+            for record in records.iter() {
+                log.append(record.clone()).unwrap();
+            }
+            (dir, log)
+        };
+
+        let (_dir_a, mut a) = new_log(&records);
+        let (_dir_b, mut b) = new_log(&records);
+        assert!(a.segments.len() > 2);
+        for up_to in 0..7 {
+            assert_eq!(a.merkle_root(up_to).unwrap(), b.merkle_root(up_to).unwrap());
+        }
+        assert!(!a.segment_roots.is_empty());
+        // cached segment roots give the same answer
+        assert_eq!(a.merkle_root(6).unwrap(), b.merkle_root(6).unwrap());
+        assert_ne!(a.merkle_root(5).unwrap(), a.merkle_root(6).unwrap());
+
+        let mut altered = records.clone();
+        altered[3].value = b"record X".to_vec();
+        let (_dir_c, mut c) = new_log(&altered);
+        assert_eq!(a.merkle_root(2).unwrap(), c.merkle_root(2).unwrap());
+        assert_ne!(a.merkle_root(3).unwrap(), c.merkle_root(3).unwrap());
+        assert_ne!(a.merkle_root(6).unwrap(), c.merkle_root(6).unwrap());
+    }
+
+    #[test]
+    fn test_read_closed_segment() {
+        for reopen in [false, true] {
+            let dir = tempfile::Builder::new()
+                .prefix("closed-segment-test")
+                .tempdir()
+                .unwrap();
+            let c = Config {
+                max_store_bytes: 32,
+                max_index_bytes: 1024,
+                reopen_closed_segments: reopen,
+                ..Default::default()
+            };
+            let mut log = Log::new(&dir, c).unwrap();
+            for _ in 0..4 {
+                let record = Record {
+                    value: b"hello".to_vec(),
+                    offset: 0,
+                };
+                log.append(record).unwrap();
+            }
+            assert!(log.segments.len() > 1);
+            log.segments[0].as_mut().unwrap().close().unwrap();
+
+            let res = log.read_at_offset(0);
+            if reopen {
+                assert_eq!(b"hello".to_vec(), res.unwrap().unwrap().value);
+            } else {
+                let err = res.unwrap_err();
+                let closed = err.get_ref().unwrap().downcast_ref::<LogError>();
+                assert_eq!(Some(&LogError::SegmentClosed { base_offset: 0 }), closed);
+            }
+            // a root over a closed segment fails the same way rather than skipping it
+            let root = log.merkle_root(0);
+            if reopen {
+                root.unwrap();
+            } else {
+                let err = root.unwrap_err();
+                let closed = err.get_ref().unwrap().downcast_ref::<LogError>();
+                assert_eq!(Some(&LogError::SegmentClosed { base_offset: 0 }), closed);
+            }
+            // segments that are still open are unaffected
+            let last = log.highest_offset().unwrap();
+            assert!(log.read_at_offset(last).unwrap().is_some());
+        }
+    }
+
+    #[derive(Debug, Default)]
+    struct FailNth {
+        n: usize,
+        calls: std::sync::atomic::AtomicUsize,
+    }
+
+    impl OffsetAssigner for FailNth {
+        fn assign(&self, next_offset: u64) -> u64 {
+            let calls = self.calls.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+            if calls + 1 == self.n {
+                // an offset the segment refuses, failing the append
+                return next_offset - 1;
+            }
+            next_offset
+        }
+    }
+
+    #[test]
+    fn test_append_atomic() {
+        let dir = tempfile::Builder::new()
+            .prefix("append-atomic-test")
+            .tempdir()
+            .unwrap();
+        let c = Config {
+            max_store_bytes: 32,
+            max_index_bytes: 1024,
+            ..Default::default()
+        };
+        let mut log = Log::new(&dir, c).unwrap();
+        let record = |value: &str| Record {
+            value: value.as_bytes().to_vec(),
+            offset: 0,
+        };
+        log.append(record("first")).unwrap();
+        let segments = log.segments.len();
+
+        // the first two records fill a segment, so the rollback has one to remove
+        log.set_offset_assigner(Arc::new(FailNth {
+            n: 3,
+            ..Default::default()
+        }));
+        let batch: Vec<Record> = ["a", "b", "c", "d"].into_iter().map(record).collect();
+        assert!(log.append_atomic(batch.clone()).is_err());
+        assert_eq!(0, log.highest_offset().unwrap());
+        assert_eq!(segments, log.segments.len());
+        assert!(log.read_at_offset(1).is_err());
+        assert!(log.read_at_offset(2).is_err());
+
+        log.set_offset_assigner(Arc::new(Sequential));
+        assert_eq!(vec![1, 2, 3, 4], log.append_atomic(batch).unwrap());
+        assert_eq!(b"c".to_vec(), log.read_at_offset(3).unwrap().unwrap().value);
+
+        // the rolled back bytes are gone from the files too
+        log.close().unwrap();
+        let mut log = Log::new(log.dir, log.config).unwrap();
+        assert_eq!(4, log.highest_offset().unwrap());
+        for (offset, value) in ["first", "a", "b", "c", "d"].into_iter().enumerate() {
+            let read = log.read_at_offset(offset as u64).unwrap().unwrap();
+            assert_eq!(value.as_bytes().to_vec(), read.value);
+        }
+    }
+
+    #[test]
+    fn test_sync_dir() {
+        let syncs = || segment::DIR_SYNCS.with(|syncs| syncs.borrow_mut().split_off(0));
+        for sync in [false, true] {
+            let dir = tempfile::Builder::new()
+                .prefix("sync-dir-test")
+                .tempdir()
+                .unwrap();
+            let c = Config {
+                max_store_bytes: 32,
+                max_index_bytes: 1024,
+                sync_dir: sync,
+                ..Default::default()
+            };
+            syncs();
+            let mut log = Log::new(&dir, c).unwrap();
+            let created = syncs();
+            for _ in 0..3 {
+                let record = Record {
+                    value: b"hello world".to_vec(),
+                    offset: 0,
+                };
+                log.append(record).unwrap();
+            }
+            let rolled = syncs();
+            log.truncate(2).unwrap();
+            let removed = syncs();
+
+            if sync {
+                assert_eq!(vec![dir.path().to_path_buf()], created);
+                assert_eq!(1, rolled.len());
+                assert_eq!(1, removed.len());
+            } else {
+                assert!(created.is_empty() && rolled.is_empty() && removed.is_empty());
+            }
+        }
+    }
+
+    #[test]
+    fn test_diff() {
+        let new_log = |values: &[&str], stride: u64| {
+            let dir = tempfile::Builder::new()
+                .prefix("diff-test")
+                .tempdir()
+                .unwrap();
+            let c = Config {
+                max_store_bytes: 32,
+                max_index_bytes: 1024,
+                ..Default::default()
+            };
+            let mut log = Log::new(&dir, c).unwrap();
+            log.set_offset_assigner(Arc::new(Stride(stride)));
+            for value in values {
+                let record = Record {
+                    value: value.as_bytes().to_vec(),
+                    offset: 0,
+                };
+                log.append(record).unwrap();
+            }
+            (dir, log)
+        };
+
+        let values = ["a", "b", "c", "d", "e"];
+        let (_dir_a, mut a) = new_log(&values, 1);
+        let (_dir_b, mut b) = new_log(&values, 1);
+        assert!(a.diff(&mut b).unwrap().is_empty());
+
+        let (_dir_c, mut c) = new_log(&["a", "b", "x", "d", "e", "f", "g"], 1);
+        assert_eq!(
+            vec![
+                OffsetDiff::ValueDiffers(2),
+                OffsetDiff::OnlyInOther(5),
+                OffsetDiff::OnlyInOther(6),
+            ],
+            a.diff(&mut c).unwrap()
+        );
+        assert_eq!(
+            vec![
+                OffsetDiff::ValueDiffers(2),
+                OffsetDiff::OnlyInSelf(5),
+                OffsetDiff::OnlyInSelf(6),
+            ],
+            c.diff(&mut a).unwrap()
+        );
+
+        // offsets skipped by one log are missing from it, not errors
+        let (_dir_d, mut d) = new_log(&["a", "b", "c"], 2);
+        assert_eq!(
+            vec![
+                OffsetDiff::OnlyInSelf(1),
+                OffsetDiff::ValueDiffers(2),
+                OffsetDiff::OnlyInSelf(3),
+            ],
+            a.diff(&mut d).unwrap()[..3].to_vec()
+        );
+
+        let (_dir_e, mut empty) = new_log(&[], 1);
+        assert_eq!(5, a.diff(&mut empty).unwrap().len());
+        let (_dir_f, mut other_empty) = new_log(&[], 1);
+        assert!(empty.diff(&mut other_empty).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_highest_offset_after_roll() {
+        let dir = tempfile::Builder::new()
+            .prefix("highest-offset-test")
+            .tempdir()
+            .unwrap();
+        let c = Config {
+            max_store_bytes: 32,
+            max_index_bytes: 1024,
+            ..Default::default()
+        };
+        let mut log = Log::new(&dir, c).unwrap();
+        for want in 0..6u64 {
+            let segments = log.segments.len();
+            let record = Record {
+                value: b"hello world".to_vec(),
+                offset: 0,
+            };
+            assert_eq!(want, log.append(record).unwrap());
+            assert_eq!(want, log.highest_offset().unwrap());
+            if log.segments.len() > segments {
+                // the roll left an empty segment active
+                assert_eq!(
+                    want + 1,
+                    log.segments.last().unwrap().as_ref().unwrap().base_offset
+                );
+            }
+        }
+        assert!(log.segments.len() > 2);
+    }
+
+    #[test]
+    fn test_append_detailed() {
+        let dir = tempfile::Builder::new()
+            .prefix("append-detailed-test")
+            .tempdir()
+            .unwrap();
+        let record = Record {
+            value: b"hello world".to_vec(),
+            offset: 0,
+        };
+        let width = |offset: u64| {
+            let mut buf = Vec::new();
+            Record {
+                offset,
+                ..record.clone()
+            }
+            .encode(&mut buf)
+            .unwrap();
+            buf.len() as u64 + HEADER_WIDTH
+        };
+        let c = Config {
+            max_store_bytes: width(0) + width(1),
+            max_index_bytes: 1024,
+            ..Default::default()
+        };
+        let mut log = Log::new(&dir, c).unwrap();
+
+        let got: Vec<AppendResult> = (0..3)
+            .map(|_| log.append_detailed(record.clone()).unwrap())
+            .collect();
+        let want = vec![
+            AppendResult {
+                offset: 0,
+                base_offset: 0,
+                position: 0,
+                rolled: false,
+            },
+            AppendResult {
+                offset: 1,
+                base_offset: 0,
+                position: width(0),
+                rolled: true,
+            },
+            AppendResult {
+                offset: 2,
+                base_offset: 2,
+                position: 0,
+                rolled: false,
+            },
+        ];
+        assert_eq!(want, got);
+        assert_eq!(
+            2,
+            log.segments[log.active_segment]
+                .as_ref()
+                .unwrap()
+                .base_offset
+        );
+    }
+
+    #[test]
+    fn test_log_dir_checks() {
+        let root = tempfile::Builder::new()
+            .prefix("log-dir-test")
+            .tempdir()
+            .unwrap();
+        let c = Config {
+            create_dir: true,
+            ..Default::default()
+        };
+
+        let missing = root.path().join("missing");
+        let err = Log::new(&missing, Config::default())
+            .map(|_| ())
+            .map_err(|e| e.kind());
+        assert_eq!(err, Err(ErrorKind::NotFound));
+        let mut log = Log::new(&missing, c.clone()).unwrap();
+        assert!(missing.is_dir());
+        let record = Record {
+            value: b"hello".to_vec(),
+            offset: 0,
+        };
+        assert_eq!(0, log.append(record).unwrap());
+
+        let file = root.path().join("file");
+        std::fs::write(&file, b"not a log").unwrap();
+        let err = Log::new(&file, c.clone()).map(|_| ()).map_err(|e| e.kind());
+        assert_eq!(err, Err(ErrorKind::NotADirectory));
+
+        // an existing directory is used as is
+        let log = Log::new(&missing, c).unwrap();
+        assert_eq!(0, log.highest_offset().unwrap());
+    }
+
+    #[test]
+    fn test_log_readers() {
+        let dir = tempfile::Builder::new()
+            .prefix("log-readers-test")
+            .tempdir()
+            .unwrap();
+        let c = Config {
+            max_store_bytes: 32,
+            ..Default::default()
+        };
+        let mut log = Log::new(&dir, c).unwrap();
+        let record = Record {
+            value: b"hello world".to_vec(),
+            offset: 0,
+        };
+        for _ in 0..5 {
+            log.append(record.clone()).unwrap();
+        }
+        log.flush().unwrap();
+        assert!(log.segments.len() > 1);
+
+        // two readers interleaved each see every byte once, in order
+        let (mut a, mut b) = (log.reader().unwrap(), log.reader().unwrap());
+        let (mut got_a, mut got_b) = (Vec::new(), Vec::new());
+        let mut chunk = [0u8; 7];
+        loop {
+            let n = a.read(&mut chunk).unwrap();
+            got_a.extend_from_slice(&chunk[..n]);
+            let m = b.read(&mut chunk[..3]).unwrap();
+            got_b.extend_from_slice(&chunk[..m]);
+            if n == 0 && m == 0 {
+                break;
+            }
+        }
+        let want: Vec<u8> = log
+            .segments
+            .iter()
+            .flatten()
+            .flat_map(|s| {
+                std::fs::read(dir.path().join(format!("{}.store", s.base_offset))).unwrap()
+            })
+            .collect();
+        assert_eq!(want, got_a);
+        assert_eq!(want, got_b);
+
+        // a reader stops where the log was when it was created
+        let mut before = log.reader().unwrap();
+        log.append(record).unwrap();
+        log.flush().unwrap();
+        let mut got = Vec::new();
+        before.read_to_end(&mut got).unwrap();
+        assert_eq!(want, got);
+        let mut got = Vec::new();
+        log.reader().unwrap().read_to_end(&mut got).unwrap();
+        assert!(got.len() > want.len());
+    }
+
+    #[test]
+    fn test_scan_range() {
+        let dir = tempfile::Builder::new()
+            .prefix("scan-range-test")
+            .tempdir()
+            .unwrap();
+        let c = Config {
+            max_store_bytes: 32,
+            ..Default::default()
+        };
+        let mut log = Log::new(&dir, c).unwrap();
+        for _ in 0..5 {
+            let record = Record {
+                value: b"hello world".to_vec(),
+                offset: 0,
+            };
+            log.append(record).unwrap();
+        }
+
+        // visiting stops as soon as the callback says so
+        let mut seen = Vec::new();
+        log.scan_range(1, u64::MAX, |record| {
+            seen.push(record.offset);
+            seen.len() < 2
+        })
+        .unwrap();
+        assert_eq!(vec![1, 2], seen);
+
+        let offsets: Vec<u64> = log
+            .read_range(0, u64::MAX)
+            .unwrap()
+            .iter()
+            .map(|record| record.offset)
+            .collect();
+        assert_eq!(vec![0, 1, 2, 3, 4], offsets);
+        let err = log.scan_range(5, 10, |_| true).unwrap_err();
+        assert_eq!(ErrorKind::NotFound, err.kind());
+    }
+
+    #[test]
+    fn test_index_entries() {
+        let dir = tempfile::Builder::new()
+            .prefix("index-entries-test")
+            .tempdir()
+            .unwrap();
+        let c = Config {
+            max_store_bytes: 64,
+            ..Default::default()
+        };
+        let mut log = Log::new(&dir, c).unwrap();
+        for i in 0..10 {
+            let record = Record {
+                value: vec![b'x'; i * 3],
+                offset: 0,
+            };
+            log.append(record).unwrap();
+        }
+        assert!(log.segments.len() > 2);
+
+        let entries = log.index_entries(0, u64::MAX, usize::MAX).unwrap();
+        let offsets: Vec<u64> = entries.iter().map(|entry| entry.offset).collect();
+        assert_eq!((0..10).collect::<Vec<u64>>(), offsets);
+        for segment in log.segments.iter_mut().flatten() {
+            let mut bytes = 0;
+            let range = segment.base_offset..segment.next_offset;
+            for entry in entries.iter().filter(|entry| range.contains(&entry.offset)) {
+                assert_eq!(segment.position_of(entry.offset).unwrap(), entry.position);
+                bytes += entry.len;
+            }
+            // every record's frame is covered once
+            assert_eq!(segment.store_bytes(), bytes);
+        }
+
+        let entries = log.index_entries(3, 7, usize::MAX).unwrap();
+        let offsets: Vec<u64> = entries.iter().map(|entry| entry.offset).collect();
+        assert_eq!(vec![3, 4, 5, 6, 7], offsets);
+        let entries = log.index_entries(3, 7, 2).unwrap();
+        assert_eq!(
+            vec![3, 4],
+            entries.iter().map(|e| e.offset).collect::<Vec<_>>()
+        );
+        assert!(log.index_entries(10, u64::MAX, 10).unwrap().is_empty());
+        assert!(log.index_entries(7, 3, 10).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_initial_offset() {
+        let dir = tempfile::Builder::new()
+            .prefix("initial-offset-test")
+            .tempdir()
+            .unwrap();
+        let c = Config {
+            max_store_bytes: 32,
+            initial_offset: 1000,
+            ..Default::default()
+        };
+        let mut log = Log::new(&dir, c.clone()).unwrap();
+        assert_eq!(1000, log.lowest_offset().unwrap());
+        assert_eq!(1000, log.next_offset());
+        assert!(dir.path().join("1000.store").exists());
+
+        let record = Record {
+            value: b"hello world".to_vec(),
+            offset: 0,
+        };
+        for want in 1000..1003 {
+            assert_eq!(want, log.append(record.clone()).unwrap());
+        }
+        assert!(log.segments.len() > 1);
+        for offset in 1000..1003 {
+            let read = log.read_at_offset(offset).unwrap().unwrap();
+            assert_eq!(offset, read.offset);
+            assert_eq!(record.value, read.value);
+        }
+        assert_eq!(1000, log.lowest_offset().unwrap());
+        assert_eq!(1002, log.highest_offset().unwrap());
+        let err = log.read_at_offset(999).unwrap_err();
+        assert_eq!(ErrorKind::NotFound, err.kind());
+
+        // existing segments win over initial_offset on reopen
+        log.close().unwrap();
+        let mut log = Log::new(&dir, c).unwrap();
+        assert_eq!(1000, log.lowest_offset().unwrap());
+        assert_eq!(1003, log.append(record).unwrap());
+    }
+
+    #[test]
+    fn test_append_batch() {
+        let dir = tempfile::Builder::new()
+            .prefix("append-batch-test")
+            .tempdir()
+            .unwrap();
+        let c = Config {
+            max_store_bytes: 32,
+            ..Default::default()
+        };
+        let mut log = Log::new(&dir, c).unwrap();
+        let record = Record {
+            value: b"hello world".to_vec(),
+            offset: 0,
+        };
+        log.append(record.clone()).unwrap();
+
+        let got = log.append_batch(vec![record.clone(); 4]).unwrap();
+        assert_eq!(1..5, got);
+        assert!(log.segments.len() > 1);
+        assert_eq!(5..5, log.append_batch(Vec::new()).unwrap());
+
+        // every store is flushed without a call to flush
+        let on_disk: u64 = log
+            .segments
+            .iter()
+            .flatten()
+            .map(|s| {
+                std::fs::metadata(dir.path().join(format!("{}.store", s.base_offset)))
+                    .unwrap()
+                    .len()
+            })
+            .sum();
+        let stored: u64 = log
+            .segments
+            .iter()
+            .flatten()
+            .map(Segment::store_bytes)
+            .sum();
+        assert_eq!(stored, on_disk);
+        for offset in got {
+            let read = log.read_at_offset(offset).unwrap().unwrap();
+            assert_eq!(record.value, read.value);
+            assert_eq!(offset, read.offset);
+        }
+    }
+
+    #[test]
+    fn test_reader_from() {
+        let dir = tempfile::Builder::new()
+            .prefix("reader-from-test")
+            .tempdir()
+            .unwrap();
+        let c = Config {
+            max_store_bytes: 32,
+            ..Default::default()
+        };
+        let mut log = Log::new(&dir, c).unwrap();
+        for _ in 0..5 {
+            let record = Record {
+                value: b"hello world".to_vec(),
+                offset: 0,
+            };
+            log.append(record).unwrap();
+        }
+        assert!(log.segments.len() > 1);
+
+        let mut all = Vec::new();
+        log.reader_from(0).unwrap().read_to_end(&mut all).unwrap();
+        log.flush().unwrap();
+        let mut want = Vec::new();
+        log.reader().unwrap().read_to_end(&mut want).unwrap();
+        assert_eq!(want, all);
+
+        for offset in 1..5 {
+            let mut got = Vec::new();
+            log.reader_from(offset)
+                .unwrap()
+                .read_to_end(&mut got)
+                .unwrap();
+            assert!(all.ends_with(&got));
+            // the first frame holds the requested record
+            let prefix = log.config.byte_order.read_u64(&got[..8]);
+            let size = (prefix & ((1 << 56) - 1)) as usize;
+            let start = HEADER_WIDTH as usize;
+            let record = Record::decode(&got[start..start + size]).unwrap();
+            assert_eq!(offset, record.offset);
+        }
+
+        for offset in [5, 100] {
+            let err = log.reader_from(offset).unwrap_err();
+            assert_eq!(ErrorKind::NotFound, err.kind());
+        }
+        log.truncate(2).unwrap();
+        let lowest = log.lowest_offset().unwrap();
+        assert!(lowest > 0);
+        let err = log.reader_from(lowest - 1).unwrap_err();
+        assert_eq!(ErrorKind::NotFound, err.kind());
+    }
+
+    #[test]
+    fn test_enforce_retention() {
+        let dir = tempfile::Builder::new()
+            .prefix("retention-test")
+            .tempdir()
+            .unwrap();
+        let mut c = Config {
+            max_store_bytes: 32,
+            max_index_bytes: 1024,
+            ..Default::default()
+        };
+        let mut log = Log::new(&dir, c.clone()).unwrap();
+        for _ in 0..9 {
+            let record = Record {
+                value: b"hello".to_vec(),
+                offset: 0,
+            };
+            log.append(record).unwrap();
+        }
+        // without a limit nothing is removed
+        assert_eq!(0, log.enforce_retention().unwrap());
+        let segments = log.segments.len();
+        let store_bytes: Vec<u64> = log
+            .segments
+            .iter()
+            .flatten()
+            .map(Segment::store_bytes)
+            .collect();
+        log.close().unwrap();
+
+        // keep the newest two segments' worth of bytes
+        c.retention_max_bytes = Some(store_bytes[segments - 2..].iter().sum());
+        let mut log = Log::new(&dir, c.clone()).unwrap();
+        assert_eq!(segments - 2, log.enforce_retention().unwrap());
+        assert_eq!(2, log.segments.len());
+        assert_eq!(1, log.active_segment);
+        let lowest = log.lowest_offset().unwrap();
+        assert!(log.read_at_offset(lowest - 1).is_err());
+        assert_eq!(8, log.read_at_offset(8).unwrap().unwrap().offset);
+
+        // the active segment stays even over the limit
+        log.config.retention_max_bytes = Some(0);
+        assert_eq!(1, log.enforce_retention().unwrap());
+        assert_eq!(1, log.segments.len());
+        assert_eq!(0, log.active_segment);
+        let next = log
+            .append(Record {
+                value: b"hello".to_vec(),
+                offset: 0,
+            })
+            .unwrap();
+        assert_eq!(next, log.read_at_offset(next).unwrap().unwrap().offset);
+        log.close().unwrap();
+
+        // background tasks enforce it every interval
+        let dir = tempfile::Builder::new()
+            .prefix("retention-interval-test")
+            .tempdir()
+            .unwrap();
+        c.retention_max_bytes = Some(0);
+        c.retention_interval = Some(Duration::from_millis(10));
+        let mut log = Log::new(&dir, c).unwrap();
+        for _ in 0..9 {
+            let record = Record {
+                value: b"hello".to_vec(),
+                offset: 0,
+            };
+            log.append(record).unwrap();
+        }
+        assert!(log.segments.len() > 1);
+        let log = Arc::new(RwLock::new(log));
+        let tasks = Log::spawn_background_tasks(&log).unwrap();
+        std::thread::sleep(Duration::from_millis(60));
+        assert_eq!(1, log.read().unwrap().segments.len());
+        log.write().unwrap().close().unwrap();
+        tasks.thread().unpark();
+        tasks.join().unwrap();
+    }
+
+    #[test]
+    fn test_enforce_retention_age() {
+        let dir = tempfile::Builder::new()
+            .prefix("retention-age-test")
+            .tempdir()
+            .unwrap();
+        let mut c = Config {
+            max_store_bytes: 32,
+            max_index_bytes: 1024,
+            ..Default::default()
+        };
+        let mut log = Log::new(&dir, c.clone()).unwrap();
+        for _ in 0..9 {
+            let record = Record {
+                value: b"hello".to_vec(),
+                offset: 0,
+            };
+            log.append(record).unwrap();
+        }
+        let bases: Vec<u64> = log
+            .segments
+            .iter()
+            .flatten()
+            .map(|s| s.base_offset)
+            .collect();
+        assert!(bases.len() >= 4);
+        log.close().unwrap();
+
+        // back-date the first two segments and the active one by a day
+        let day_ago = SystemTime::now() - Duration::from_secs(24 * 60 * 60);
+        let millis = day_ago
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap()
+            .as_millis() as u64;
+        for base in [bases[0], bases[1], *bases.last().unwrap()] {
+            let path = dir
+                .path()
+                .join(format!("{}.{}", base, segment::META_EXTENSION));
+            let mut meta = std::fs::read(&path).unwrap();
+            byteorder::BigEndian::write_u64(&mut meta[16..24], millis);
+            std::fs::write(&path, meta).unwrap();
+        }
+
+        c.retention_max_age = Some(Duration::from_secs(60 * 60));
+        let mut log = Log::new(&dir, c).unwrap();
+        assert_eq!(2, log.enforce_retention().unwrap());
+        let left: Vec<u64> = log
+            .segments
+            .iter()
+            .flatten()
+            .map(|s| s.base_offset)
+            .collect();
+        assert_eq!(bases[2..].to_vec(), left);
+        assert_eq!(left.len() - 1, log.active_segment);
+        // nothing else is stale bar the active segment, which stays
+        assert_eq!(0, log.enforce_retention().unwrap());
+        assert_eq!(
+            bases[2],
+            log.read_at_offset(bases[2]).unwrap().unwrap().offset
+        );
+    }
+
+    #[test]
+    fn test_merge_keeps_write_times() {
+        let dir = tempfile::Builder::new()
+            .prefix("merge-write-times-test")
+            .tempdir()
+            .unwrap();
+        let mut c = Config {
+            max_store_bytes: 32,
+            max_index_bytes: 1024,
+            ..Default::default()
+        };
+        let mut log = Log::new(&dir, c.clone()).unwrap();
+        for _ in 0..9 {
+            let record = Record {
+                value: b"hello".to_vec(),
+                offset: 0,
+            };
+            log.append(record).unwrap();
+        }
+        let bases: Vec<u64> = log
+            .segments
+            .iter()
+            .flatten()
+            .map(|s| s.base_offset)
+            .collect();
+        assert!(bases.len() >= 4);
+        log.close().unwrap();
+
+        // back-date the first two segments by a day and two days
+        let day = Duration::from_secs(24 * 60 * 60);
+        for (base, age) in [(bases[0], 2 * day), (bases[1], day)] {
+            let millis = (SystemTime::now() - age)
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .unwrap()
+                .as_millis() as u64;
+            let path = dir
+                .path()
+                .join(format!("{}.{}", base, segment::META_EXTENSION));
+            let mut meta = std::fs::read(&path).unwrap();
+            byteorder::BigEndian::write_u64(&mut meta[16..24], millis);
+            byteorder::BigEndian::write_u64(&mut meta[24..32], millis);
+            std::fs::write(&path, meta).unwrap();
+        }
+
+        let mut log = Log::new(&dir, c.clone()).unwrap();
+        let old = log.segments[0].as_ref().unwrap().last_write();
+        assert_eq!(bases[0], log.merge_segments(&bases[..2]).unwrap());
+        let merged = log.segments[0].as_ref().unwrap();
+        // the newest of the inputs' last writes, not the merged files' mtime
+        assert!(merged.last_write().unwrap() < SystemTime::now() - day / 2);
+        assert!(merged.last_write() > old);
+        assert!(merged.active_since() <= old);
+        log.close().unwrap();
+
+        c.retention_max_age = Some(Duration::from_secs(60 * 60));
+        let mut log = Log::new(&dir, c).unwrap();
+        assert_eq!(1, log.enforce_retention().unwrap());
+        let left: Vec<u64> = log
+            .segments
+            .iter()
+            .flatten()
+            .map(|s| s.base_offset)
+            .collect();
+        assert_eq!(bases[2..].to_vec(), left);
+    }
+
+    #[test]
+    fn test_setup_missing_index() {
+        let dir = tempfile::Builder::new()
+            .prefix("setup-missing-index-test")
+            .tempdir()
+            .unwrap();
+        let c = Config {
+            max_store_bytes: 32,
+            max_index_bytes: 1024,
+            ..Default::default()
+        };
+        let mut log = Log::new(&dir, c.clone()).unwrap();
+        for _ in 0..6 {
+            let record = Record {
+                value: b"hello".to_vec(),
+                offset: 0,
+            };
+            log.append(record).unwrap();
+        }
+        let second = log.segments[1].as_ref().unwrap().base_offset;
+        let segments = log.segments.len();
+        log.close().unwrap();
+
+        // every segment opens once, however many files it has
+        let mut log = Log::new(&dir, c.clone()).unwrap();
+        assert_eq!(segments, log.segments.len());
+        log.close().unwrap();
+
+        std::fs::remove_file(dir.path().join(format!("{}.index", second))).unwrap();
+        let err = Log::new(&dir, c).map(|_| ()).unwrap_err();
+        assert_eq!(ErrorKind::InvalidData, err.kind());
+        assert_eq!(
+            format!("segment {} has a store file but no index file", second),
+            err.to_string()
+        );
+    }
+
+    #[test]
+    fn test_recover_on_open() {
+        let c = Config {
+            max_store_bytes: 1024,
+            recover_on_open: true,
+            ..Default::default()
+        };
+        let record = Record {
+            value: b"hello world".to_vec(),
+            offset: 0,
+        };
+        // the last record cut mid-payload, and with none of its bytes in the store
+        for torn_mid_record in [true, false] {
+            let dir = tempfile::Builder::new()
+                .prefix("recover-on-open-test")
+                .tempdir()
+                .unwrap();
+            let mut log = Log::new(&dir, c.clone()).unwrap();
+            for _ in 0..2 {
+                log.append(record.clone()).unwrap();
+            }
+            let last = log.append_detailed(record.clone()).unwrap();
+            log.close().unwrap();
+            let store = dir.path().join("0.store");
+            let keep = match torn_mid_record {
+                true => std::fs::metadata(&store).unwrap().len() - 3,
+                false => last.position,
+            };
+            std::fs::OpenOptions::new()
+                .write(true)
+                .open(&store)
+                .unwrap()
+                .set_len(keep)
+                .unwrap();
+
+            let mut log = Log::new(&dir, c.clone()).unwrap();
+            assert_eq!(2, log.next_offset());
+            let read = log.read_at_offset(1).unwrap().unwrap();
+            assert_eq!(record.value, read.value);
+            let err = log.read_at_offset(2).unwrap_err();
+            assert_eq!(ErrorKind::NotFound, err.kind());
+
+            // the recovered log carries on from the last intact record
+            assert_eq!(2, log.append(record.clone()).unwrap());
+            log.close().unwrap();
+            let mut log = Log::new(&dir, c.clone()).unwrap();
+            assert_eq!(2, log.read_at_offset(2).unwrap().unwrap().offset);
+        }
+    }
+
+    #[test]
+    fn test_byte_order() {
+        let dir = tempfile::Builder::new()
+            .prefix("byte-order-test")
+            .tempdir()
+            .unwrap();
+        let little = Config {
+            max_store_bytes: 32,
+            max_index_bytes: 1024,
+            byte_order: ByteOrderKind::LittleEndian,
+            ..Default::default()
+        };
+        let record = Record {
+            value: b"hello".to_vec(),
+            offset: 0,
+        };
+
+        let mut log = Log::new(&dir, little.clone()).unwrap();
+        for _ in 0..5 {
+            log.append(record.clone()).unwrap();
+        }
+        log.close().unwrap();
+        let index = std::fs::read(dir.path().join("2.index")).unwrap();
+        let entry = &index[HDRWIDTH + ENTWIDTH..HDRWIDTH + ENTWIDTH + OFFWIDTH];
+        assert_eq!(1, byteorder::LittleEndian::read_u32(entry));
+
+        let mut log = Log::new(&dir, little.clone()).unwrap();
+        assert_eq!(
+            b"hello".to_vec(),
+            log.read_at_offset(3).unwrap().unwrap().value
+        );
+        let err = Log::new(&dir, Config::default())
+            .map(|_| ())
+            .map_err(|e| e.kind());
+        assert_eq!(err, Err(ErrorKind::InvalidInput));
+
+        // a log from before the byte order was recorded is big-endian
+        std::fs::remove_file(dir.path().join(BYTE_ORDER_FILE)).unwrap();
+        let err = Log::new(&dir, little).map(|_| ()).map_err(|e| e.kind());
+        assert_eq!(err, Err(ErrorKind::InvalidInput));
+    }
+
+    #[test]
+    fn test_transforms_recorded() {
+        use crate::log::transform::{
+            tests::{RunLength, Xor},
+            Transforms,
+        };
+
+        let dir = tempfile::Builder::new()
+            .prefix("transforms-recorded-test")
+            .tempdir()
+            .unwrap();
+        let c = Config {
+            max_store_bytes: 1024,
+            transforms: Arc::new(vec![Box::new(RunLength), Box::new(Xor(b"key".to_vec()))]),
+            ..Default::default()
+        };
+        let mut log = Log::new(&dir, c.clone()).unwrap();
+        log.append(Record {
+            value: b"hello".to_vec(),
+            offset: 0,
+        })
+        .unwrap();
+        log.close().unwrap();
+        assert_eq!(
+            "run_length\nxor\n",
+            std::fs::read_to_string(dir.path().join(TRANSFORMS_FILE)).unwrap()
+        );
+
+        let mut log = Log::new(&dir, c).unwrap();
+        assert_eq!(
+            b"hello".to_vec(),
+            log.read_at_offset(0).unwrap().unwrap().value
+        );
+        log.close().unwrap();
+
+        let pipelines: Vec<Transforms> = vec![
+            Arc::new(Vec::new()),
+            Arc::new(vec![Box::new(RunLength)]),
+            Arc::new(vec![Box::new(Xor(b"key".to_vec())), Box::new(RunLength)]),
+        ];
+        for transforms in pipelines {
+            let c = Config {
+                max_store_bytes: 1024,
+                transforms,
+                ..Default::default()
+            };
+            let err = Log::new(&dir, c).map(|_| ()).map_err(|e| e.kind());
+            assert_eq!(err, Err(ErrorKind::InvalidData));
+        }
+    }
+
+    #[test]
+    fn test_roll_aged_segment() {
+        let dir = tempfile::Builder::new()
+            .prefix("segment-age-test")
+            .tempdir()
+            .unwrap();
+        let c = Config {
+            max_store_bytes: 1024,
+            max_segment_age: Some(Duration::from_millis(40)),
+            ..Default::default()
+        };
+        let append = Record {
+            value: b"hello world".into(),
+            offset: 0,
+        };
+
+        let mut log = Log::new(&dir, c.clone()).unwrap();
+        std::thread::sleep(Duration::from_millis(50));
+        assert!(!log.roll_aged_segment().unwrap());
+
+        // steady appends keep the segment from going idle, but not from ageing
+        for _ in 0..3 {
+            log.append(append.clone()).unwrap();
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        std::thread::sleep(Duration::from_millis(20));
+        assert!(log.roll_aged_segment().unwrap());
+        assert_eq!(2, log.segments.len());
+        assert!(!log.roll_aged_segment().unwrap());
+
+        // an append to an aged segment rolls it as well
+        log.append(append.clone()).unwrap();
+        std::thread::sleep(Duration::from_millis(50));
+        log.append(append.clone()).unwrap();
+        assert_eq!(3, log.segments.len());
+
+        let log = Arc::new(RwLock::new(log));
+        let tasks = Log::spawn_background_tasks(&log).unwrap();
+        log.write().unwrap().append(append.clone()).unwrap();
+        std::thread::sleep(Duration::from_millis(120));
+        {
+            let mut log = log.write().unwrap();
+            assert_eq!(4, log.segments.len());
+            assert_eq!(5, log.read_at_offset(5).unwrap().unwrap().offset);
+            log.append(append.clone()).unwrap();
+            log.close().unwrap();
+        }
+        drop(log);
+        tasks.join().unwrap();
+
+        // a restart doesn't make the active segment young again
+        std::thread::sleep(Duration::from_millis(50));
+        let mut log = Log::new(&dir, c).unwrap();
+        assert!(log.roll_aged_segment().unwrap());
+    }
+
+    #[test]
+    fn test_merge_segments() {
+        let dir = tempfile::Builder::new()
+            .prefix("merge-segments-test")
+            .tempdir()
+            .unwrap();
+        let c = Config {
+            max_store_bytes: 32,
+            max_index_bytes: 1024,
+            ..Default::default()
+        };
+        let mut log = Log::new(&dir, c).unwrap();
+        for i in 0..9u8 {
+            let record = Record {
+                value: vec![b'a' + i; 5],
+                offset: 0,
+            };
+            log.append(record).unwrap();
+        }
+        let bases: Vec<u64> = log
+            .segments
+            .iter()
+            .flatten()
+            .map(|s| s.base_offset)
+            .collect();
+        assert!(bases.len() >= 4);
+        let before = log.merkle_root(8).unwrap();
+
+        let err = log
+            .merge_segments(&[bases[0], bases[2]])
+            .map_err(|e| e.kind());
+        assert_eq!(err, Err(ErrorKind::InvalidInput));
+        let err = log.merge_segments(&bases).map_err(|e| e.kind());
+        assert_eq!(err, Err(ErrorKind::InvalidInput));
+
+        assert_eq!(0, log.merge_segments(&bases[..3]).unwrap());
+        assert_eq!(bases.len() - 2, log.segments.len());
+        assert!(!dir.path().join(format!("{}.store", bases[1])).exists());
+        let check = |log: &mut Log| {
+            for offset in 0..9u64 {
+                let record = log.read_at_offset(offset).unwrap().unwrap();
+                assert_eq!(offset, record.offset);
+                assert_eq!(vec![b'a' + offset as u8; 5], record.value);
+            }
+        };
+        check(&mut log);
+        // the log's records are the same, though its segments now hash differently
+        assert_ne!(before, log.merkle_root(8).unwrap());
+
+        let record = Record {
+            value: b"after".to_vec(),
+            offset: 0,
+        };
+        assert_eq!(9, log.append(record).unwrap());
+        log.close().unwrap();
+        let mut log = Log::new(log.dir, log.config).unwrap();
+        check(&mut log);
+        assert_eq!(9, log.highest_offset().unwrap());
+    }
+
+    #[test]
+    fn test_merge_recovery() {
+        let dir = tempfile::Builder::new()
+            .prefix("merge-recovery-test")
+            .tempdir()
+            .unwrap();
+        let c = Config {
+            max_store_bytes: 32,
+            max_index_bytes: 1024,
+            ..Default::default()
+        };
+        let mut log = Log::new(&dir, c.clone()).unwrap();
+        for _ in 0..6 {
+            let record = Record {
+                value: b"hello".to_vec(),
+                offset: 0,
+            };
+            log.append(record).unwrap();
+        }
+        let bases: Vec<u64> = log
+            .segments
+            .iter()
+            .flatten()
+            .map(|s| s.base_offset)
+            .collect();
+        let mut merging: Vec<Segment> = log.segments.drain(..2).flatten().collect();
+        let path = |ext: &str| dir.path().join(format!("0.{}", ext));
+        Segment::write_merged(&mut merging, &path("store.merge"), &path("index.merge"), &c)
+            .unwrap();
+        for segment in merging.iter_mut() {
+            segment.close().unwrap();
+        }
+        log.close().unwrap();
+
+        // crashed before any rename: the merge is thrown away
+        let backup = tempfile::Builder::new()
+            .prefix("merge-recovery-backup")
+            .tempdir()
+            .unwrap();
+        let backup = |ext: &str| backup.path().join(ext);
+        std::fs::copy(path("store.merge"), backup("store")).unwrap();
+        std::fs::copy(path("index.merge"), backup("index")).unwrap();
+        let mut reopened = Log::new(&dir, c.clone()).unwrap();
+        assert!(!path("store.merge").exists() && !path("index.merge").exists());
+        assert_eq!(bases.len(), reopened.segments.len());
+        assert_eq!(3, reopened.read_at_offset(3).unwrap().unwrap().offset);
+        reopened.close().unwrap();
+
+        // crashed after renaming the store: the merge is finished
+        std::fs::copy(backup("store"), path("store")).unwrap();
+        std::fs::copy(backup("index"), path("index.merge")).unwrap();
+        let mut reopened = Log::new(&dir, c).unwrap();
+        assert!(!path("index.merge").exists());
+        assert_eq!(bases.len() - 1, reopened.segments.len());
+        assert!(!dir.path().join(format!("{}.store", bases[1])).exists());
+        for offset in 0..6 {
+            assert_eq!(
+                offset,
+                reopened.read_at_offset(offset).unwrap().unwrap().offset
+            );
+        }
+    }
+
+    #[test]
+    fn test_compaction_stats() {
+        let dir = tempfile::Builder::new()
+            .prefix("compaction-stats-test")
+            .tempdir()
+            .unwrap();
+        let c = Config {
+            max_store_bytes: 32,
+            max_index_bytes: 1024,
+            ..Default::default()
+        };
+        let mut log = Log::new(&dir, c).unwrap();
+        for _ in 0..8 {
+            let record = Record {
+                value: b"hello".to_vec(),
+                offset: 0,
+            };
+            log.append(record).unwrap();
+        }
+        assert_eq!(CompactionStats::default(), log.compaction_stats());
+        let sizes: Vec<u64> = log
+            .segments
+            .iter()
+            .flatten()
+            .map(Segment::size_bytes)
+            .collect();
+        let bases: Vec<u64> = log
+            .segments
+            .iter()
+            .flatten()
+            .map(|s| s.base_offset)
+            .collect();
+
+        // merging rewrites everything it reads and frees nothing
+        log.merge_segments(&bases[2..4]).unwrap();
+        let stats = log.compaction_stats();
+        assert_eq!(sizes[2] + sizes[3], stats.bytes_read);
+        assert_eq!(stats.bytes_read, stats.bytes_written);
+        assert_eq!(0, stats.bytes_reclaimed);
+
+        // dropping the first half of the records frees the first two segments
+        log.truncate(bases[2]).unwrap();
+        let stats = log.compaction_stats();
+        assert_eq!(sizes[0] + sizes[1], stats.bytes_reclaimed);
+        assert_eq!(sizes[2] + sizes[3], stats.bytes_read);
     }
 }
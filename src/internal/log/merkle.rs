@@ -0,0 +1,76 @@
+use sha2::{Digest, Sha256};
+
+use crate::server::log::Record;
+
+pub type Hash = [u8; 32];
+
+// domain separation keeps a leaf from ever hashing like an inner node
+const LEAF_PREFIX: u8 = 0;
+const NODE_PREFIX: u8 = 1;
+
+pub fn leaf_hash(record: &Record) -> Hash {
+    let mut hasher = Sha256::new();
+    hasher.update([LEAF_PREFIX]);
+    hasher.update(record.offset.to_be_bytes());
+    hasher.update(&record.value);
+    hasher.finalize().into()
+}
+
+fn node_hash(left: &Hash, right: &Hash) -> Hash {
+    let mut hasher = Sha256::new();
+    hasher.update([NODE_PREFIX]);
+    hasher.update(left);
+    hasher.update(right);
+    hasher.finalize().into()
+}
+
+/// Folds the hashes pairwise into a single root, carrying an odd hash up unchanged.
+/// The root of no hashes is the hash of the empty string.
+pub fn root(hashes: &[Hash]) -> Hash {
+    if hashes.is_empty() {
+        return Sha256::digest([]).into();
+    }
+
+    let mut level = hashes.to_vec();
+    while level.len() > 1 {
+        level = level
+            .chunks(2)
+            .map(|pair| match pair {
+                [left, right] => node_hash(left, right),
+                [single] => *single,
+                _ => unreachable!(),
+            })
+            .collect();
+    }
+    level[0]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_root() {
+        let hashes: Vec<Hash> = (0..5u64)
+            .map(|offset| {
+                leaf_hash(&Record {
+                    value: b"hello world".to_vec(),
+                    offset,
+                })
+            })
+            .collect();
+
+        assert_eq!(hashes[0], root(&hashes[..1]));
+        assert_eq!(node_hash(&hashes[0], &hashes[1]), root(&hashes[..2]));
+        let want = node_hash(
+            &node_hash(
+                &node_hash(&hashes[0], &hashes[1]),
+                &node_hash(&hashes[2], &hashes[3]),
+            ),
+            &hashes[4],
+        );
+        assert_eq!(want, root(&hashes));
+        assert_ne!(root(&hashes[..4]), root(&hashes));
+        assert_eq!(root(&[]), root(&[]));
+    }
+}
@@ -1,5 +1,10 @@
+pub mod assigner;
 pub mod config;
 pub mod index;
+#[allow(clippy::module_inception)]
 pub mod log;
+pub mod merkle;
+pub mod reader;
 pub mod segment;
 pub mod store;
+pub mod transform;
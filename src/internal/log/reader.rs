@@ -0,0 +1,38 @@
+use std::{fs::File, io::Read};
+
+/// A sequential reader over a log's store bytes with its own cursor, so any number of
+/// them can scan the log without moving each other or the log's `Read` position.
+///
+/// Each segment's store is read through its own file handle, up to the length it had
+/// when the reader was created.
+#[derive(Debug)]
+pub struct LogReader {
+    segments: Vec<std::io::Take<File>>,
+    // segment being read; the byte offset in it is its handle's cursor
+    idx: usize,
+}
+
+impl LogReader {
+    pub(crate) fn new(segments: Vec<(File, u64)>) -> Self {
+        LogReader {
+            segments: segments
+                .into_iter()
+                .map(|(file, len)| file.take(len))
+                .collect(),
+            idx: 0,
+        }
+    }
+}
+
+impl Read for LogReader {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        while let Some(segment) = self.segments.get_mut(self.idx) {
+            let n = segment.read(buf)?;
+            if n > 0 || buf.is_empty() {
+                return Ok(n);
+            }
+            self.idx += 1;
+        }
+        Ok(0)
+    }
+}
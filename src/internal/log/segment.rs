@@ -1,15 +1,65 @@
 use std::{
-    fs::OpenOptions,
-    io::Read,
-    os::unix::prelude::OpenOptionsExt,
+    fs::{File, OpenOptions},
+    io::{Error, ErrorKind, Read},
     path::{Path, PathBuf},
+    sync::Arc,
+    time::{Duration, SystemTime},
 };
 
 use prost::Message;
 
-use crate::server::log::Record;
+use crate::server::log::{LogError, Record};
 
-use super::{config::Config, index::Index, store::Store};
+use super::{
+    assigner::{OffsetAssigner, Sequential},
+    config::Config,
+    index::Index,
+    merkle::{self, Hash},
+    store::Store,
+};
+
+/// Where a segment's files and offsets stood, for rolling back appends made since.
+#[derive(Debug, Clone, Copy)]
+pub struct Checkpoint {
+    store_size: u64,
+    next_offset: u64,
+    last_index_pos: Option<u64>,
+    last_append_bytes: u64,
+    records: u64,
+    active_since: Option<SystemTime>,
+    last_write: Option<SystemTime>,
+}
+
+/// A record as the index places it in the store, without reading the store.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IndexEntry {
+    pub offset: u64,
+    /// Store position of the record's frame.
+    pub position: u64,
+    /// Store bytes up to the next entry's frame, or the end of the store. With a dense
+    /// index that's the record's frame; a sparse one covers the records in between too.
+    pub len: u64,
+}
+
+/// Extension of the files a merge writes before they replace the first merged segment's.
+pub const MERGE_EXTENSION: &str = "merge";
+
+/// Extension of the sidecar a segment writes on close, recording its offsets and when
+/// it was first and last written.
+pub const META_EXTENSION: &str = "meta";
+// encoded base offset, next offset, then last and first write in milliseconds since the
+// epoch, 0 when the segment has no records
+const META_WIDTH: usize = 32;
+// sidecars written before the first write was recorded
+const LEGACY_META_WIDTH: usize = 24;
+
+// what a segment's sidecar recorded at its last close
+#[derive(Debug, Clone, Copy)]
+struct Meta {
+    next_offset: u64,
+    last_write: Option<SystemTime>,
+    first_write: Option<SystemTime>,
+}
 
 #[derive(Debug)]
 pub struct Segment {
@@ -20,6 +70,20 @@ pub struct Segment {
     config: Config,
     index_name: PathBuf,
     store_name: PathBuf,
+    meta_name: PathBuf,
+    // store position of the most recently indexed record
+    last_index_pos: Option<u64>,
+    // store bytes taken by the most recent append
+    last_append_bytes: u64,
+    // records in the store, which assigners that skip offsets can leave fewer than the
+    // offsets covered; only counted when `Config::min_records_per_segment` needs it
+    records: u64,
+    assigner: Arc<dyn OffsetAssigner>,
+    // wall clock time of the oldest record, kept across reopens in the sidecar;
+    // `Config::max_segment_age` counts from here
+    active_since: Option<SystemTime>,
+    // wall clock time of the newest record, for time-based retention
+    last_write: Option<SystemTime>,
 }
 
 impl Segment {
@@ -27,28 +91,52 @@ impl Segment {
         let store_filename = format!("{}{}", base_offset, ".store");
         let dir = dir.as_ref();
         let store_path = dir.join(&store_filename);
-        let store_file = OpenOptions::new()
-            .mode(0o644)
-            .append(true)
-            .read(true)
-            .create(true)
-            .open(&store_path)?;
-        let store = Store::new(store_file)?;
-
         let index_filename = format!("{}{}", base_offset, ".index");
         let index_path = dir.join(&index_filename);
-        let index_file = OpenOptions::new()
-            .mode(0o644)
-            .append(true)
-            .read(true)
-            .create(true)
-            .open(&index_path)?;
-        let index = Index::new(index_file, c, &index_path)?;
+        let meta_path = dir.join(format!("{}.{}", base_offset, META_EXTENSION));
+        let (mut store, mut index) = open_store_and_index(&store_path, &index_path, &c)?;
+
+        let mut meta = read_meta(&meta_path, base_offset, &c)?;
+        if c.recover_on_open && !c.read_only && recover_torn(&mut store, &mut index)? {
+            // the sidecar counted the records that were just cut
+            meta = None;
+        }
+        let mut next_offset = meta.map_or(base_offset, |meta| meta.next_offset);
+        let mut last_index_pos = None;
+        if let Ok((_offset, pos)) = index.read(-1) {
+            last_index_pos = Some(pos);
+            // with a sparse index the records after the last entry are only found in the store
+            let mut pos = pos;
+            while meta.is_none() && pos < store.size() {
+                let (buf, next) = store.read_frame(pos)?;
+                let record: Record = Message::decode(&buf[..])?;
+                next_offset = record.offset + 1;
+                pos = next;
+            }
+        }
 
-        let next_offset = if let Ok((offset, _pos)) = index.read(-1) {
-            base_offset + offset as u64 + 1
-        } else {
-            base_offset
+        let records = match c.min_records_per_segment {
+            0 => 0,
+            // a dense index has an entry per record
+            _ if c.index_interval_bytes == 0 => index.size() / index.entry_width(),
+            _ => store.iter().take_while(Result::is_ok).count() as u64,
+        };
+
+        let last_write = match meta {
+            Some(meta) => meta.last_write,
+            // without the sidecar the store was last written when it was last modified
+            None if store.size() > 0 => std::fs::metadata(&store_path)?.modified().ok(),
+            None => None,
+        };
+        let active_since = match meta.and_then(|meta| meta.first_write) {
+            Some(first_write) => Some(first_write),
+            // otherwise the oldest record is no older than the store file, or its last
+            // modification where creation times aren't kept
+            None if next_offset > base_offset => {
+                let fi = std::fs::metadata(&store_path)?;
+                fi.created().or_else(|_| fi.modified()).ok()
+            }
+            None => None,
         };
 
         let segment = Segment {
@@ -59,11 +147,31 @@ impl Segment {
             store: Some(store),
             index_name: index_path,
             store_name: store_path,
+            meta_name: meta_path,
+            last_index_pos,
+            last_append_bytes: 0,
+            records,
+            assigner: Arc::new(Sequential),
+            active_since,
+            last_write,
         };
 
+        // appends will outdate the sidecar, so don't leave it for a crash to trust
+        segment.discard_meta()?;
         Ok(segment)
     }
 
+    /// Removes the offsets sidecar left by the last close, if the segment may be written.
+    pub fn discard_meta(&self) -> std::io::Result<()> {
+        if self.config.read_only {
+            return Ok(());
+        }
+        match std::fs::remove_file(&self.meta_name) {
+            Err(err) if err.kind() != ErrorKind::NotFound => Err(err),
+            _ => Ok(()),
+        }
+    }
+
     #[inline]
     fn get_store_and_index_mut(&mut self) -> Option<(&mut Store, &mut Index)> {
         let store = match self.store {
@@ -90,32 +198,219 @@ impl Segment {
         Some((store, index))
     }
 
-    pub fn append(&mut self, mut record: Record) -> std::io::Result<Option<u64>> {
-        let cur = self.next_offset;
+    pub fn set_offset_assigner(&mut self, assigner: Arc<dyn OffsetAssigner>) {
+        self.assigner = assigner;
+    }
+
+    pub fn append(&mut self, record: Record) -> std::io::Result<u64> {
+        Ok(self.append_with_position(record)?.0)
+    }
+
+    /// Appends the record, returning its offset and its position in the store. Fails with
+    /// `LogError::Closed` once the segment is closed.
+    pub fn append_with_position(&mut self, mut record: Record) -> std::io::Result<(u64, u64)> {
+        let cur = self.assigner.assign(self.next_offset);
+        if cur < self.next_offset {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!("assigned offset {} is below {}", cur, self.next_offset),
+            ));
+        }
         record.offset = cur;
-        let offset = (self.next_offset - self.base_offset) as u32;
+        let offset = cur - self.base_offset;
+        let interval = self.config.index_interval_bytes;
+        let last_index_pos = self.last_index_pos;
         let (store, index) = match self.get_store_and_index_mut() {
             Some(val) => val,
-            None => return Ok(None),
+            None => return Err(LogError::Closed.into()),
         };
         let mut buf: Vec<u8> = Vec::new();
         record.encode(&mut buf)?;
-        let (_, pos) = store.append(&buf)?;
-        index.write(offset, pos)?;
-        self.next_offset += 1;
-        Ok(Some(cur))
+        let pos = store.size();
+        let needs_entry = match last_index_pos {
+            Some(last) => pos - last >= interval,
+            None => true,
+        };
+        // make sure the index has room before touching the store
+        if needs_entry {
+            index.write(offset, pos)?;
+        }
+        let written = match store.append(&buf) {
+            Ok((written, _)) => written,
+            Err(err) => {
+                // don't leave an entry pointing at a record that was never stored
+                if needs_entry {
+                    index.truncate(offset)?;
+                }
+                return Err(err);
+            }
+        };
+        if needs_entry {
+            self.last_index_pos = Some(pos);
+        }
+        self.last_append_bytes = written;
+        self.records += 1;
+        self.next_offset = cur + 1;
+        self.active_since.get_or_insert_with(SystemTime::now);
+        self.last_write = Some(SystemTime::now());
+        Ok((cur, pos))
     }
 
     pub fn read_at_offset(&mut self, offset: u64) -> std::io::Result<Option<Record>> {
         let base_offset = self.base_offset;
         let (store, index) = match self.get_store_and_index_mut() {
             Some(val) => val,
-            None => return Ok(None),
+            None => return Err(LogError::SegmentClosed { base_offset }.into()),
+        };
+        let (_, mut pos) = index.find(offset - base_offset)?;
+        // scan forward from the nearest indexed record
+        while pos < store.size() {
+            let (buf, next) = store.read_frame(pos)?;
+            let record: Record = Message::decode(&buf[..])?;
+            if record.offset == offset {
+                return Ok(Some(record));
+            }
+            if record.offset > offset {
+                break;
+            }
+            pos = next;
+        }
+        Err(Error::from(ErrorKind::UnexpectedEof))
+    }
+
+    /// Returns the store position of the first record with an offset `>= offset`.
+    pub fn position_of(&mut self, offset: u64) -> std::io::Result<u64> {
+        let base_offset = self.base_offset;
+        let (store, index) = match self.get_store_and_index_mut() {
+            Some(val) => val,
+            None => return Err(LogError::Closed.into()),
+        };
+        let (_, mut pos) = index.find(offset.saturating_sub(base_offset))?;
+        while pos < store.size() {
+            let (buf, next) = store.read_frame(pos)?;
+            let record: Record = Message::decode(&buf[..])?;
+            if record.offset >= offset {
+                return Ok(pos);
+            }
+            pos = next;
+        }
+        Err(Error::from(ErrorKind::UnexpectedEof))
+    }
+
+    /// Returns up to `limit` entries of the index with offsets in `[from, to]`, in order.
+    pub fn index_entries(
+        &self,
+        from: u64,
+        to: u64,
+        limit: usize,
+    ) -> std::io::Result<Vec<IndexEntry>> {
+        let (store, index) = match self.get_store_and_index() {
+            Some(val) => val,
+            None => return Err(LogError::Closed.into()),
+        };
+        let base_offset = self.base_offset;
+        let count = (index.size() / index.entry_width()) as usize;
+        let mut entries = Vec::new();
+        let mut i = index.entries_below(from.saturating_sub(base_offset));
+        while i < count && entries.len() < limit {
+            let (rel, position) = index.read(i as i64)?;
+            if base_offset + rel > to {
+                break;
+            }
+            let end = match index.read(i as i64 + 1) {
+                Ok((_, next)) => next,
+                Err(err) if err.kind() == ErrorKind::UnexpectedEof => store.size(),
+                Err(err) => return Err(err),
+            };
+            entries.push(IndexEntry {
+                offset: base_offset + rel,
+                position,
+                len: end - position,
+            });
+            i += 1;
+        }
+        Ok(entries)
+    }
+
+    /// Hashes the segment's records with offsets `<= up_to`, in store order.
+    pub fn record_hashes(&mut self, up_to: u64) -> std::io::Result<Vec<Hash>> {
+        let store = match self.store {
+            Some(ref mut store) => store,
+            None => return Err(LogError::Closed.into()),
+        };
+        let mut hashes = Vec::new();
+        let mut pos = 0;
+        while pos < store.size() {
+            let (buf, next) = store.read_frame(pos)?;
+            let record: Record = Message::decode(&buf[..])?;
+            if record.offset > up_to {
+                break;
+            }
+            hashes.push(merkle::leaf_hash(&record));
+            pos = next;
+        }
+        Ok(hashes)
+    }
+
+    /// Decodes every record straight from the store, ignoring the index, for when the
+    /// index is lost. A truncated trailing record is skipped.
+    pub fn read_all_from_store(&mut self) -> std::io::Result<Vec<Record>> {
+        let store = match self.store {
+            Some(ref mut store) => store,
+            None => return Err(LogError::Closed.into()),
+        };
+        let mut records = Vec::new();
+        for buf in store.records() {
+            records.push(Message::decode(&buf?[..])?);
+        }
+        Ok(records)
+    }
+
+    /// When the segment's newest record was written, if it has any.
+    pub fn last_write(&self) -> Option<SystemTime> {
+        self.last_write
+    }
+
+    /// Bytes the segment's store takes on disk, read from the file when it's closed.
+    pub fn store_bytes(&self) -> u64 {
+        match self.store {
+            Some(ref store) => store.size(),
+            None => std::fs::metadata(&self.store_name).map_or(0, |meta| meta.len()),
+        }
+    }
+
+    /// Opens a separate handle on the store, returned with the bytes in it that are
+    /// already flushed, for a reader with its own cursor.
+    pub fn open_store_snapshot(&self) -> std::io::Result<(File, u64)> {
+        let file = File::open(&self.store_name)?;
+        let len = match self.store {
+            Some(ref store) => store.flushed_size(),
+            None => file.metadata()?.len(),
         };
-        let (_, pos) = index.read((offset - base_offset) as i64)?;
-        let buf = store.read_at_offset(pos)?;
-        let record: Record = Message::decode(&buf[..])?;
-        Ok(Some(record))
+        Ok((file, len))
+    }
+
+    /// Opens a separate handle on the index, returned with the bytes at its start that
+    /// hold the header and entries.
+    pub fn open_index_snapshot(&self) -> std::io::Result<(File, u64)> {
+        let file = File::open(&self.index_name)?;
+        let len = match self.index {
+            Some(ref index) => index.file_size(),
+            None => file.metadata()?.len(),
+        };
+        Ok((file, len))
+    }
+
+    /// Bytes the segment's records and index entries take up.
+    pub fn size_bytes(&self) -> u64 {
+        self.get_store_and_index()
+            .map_or(0, |(store, index)| store.size() + index.size())
+    }
+
+    /// Wall clock time of the segment's oldest record, which `Config::max_segment_age`
+    /// counts from.
+    pub fn active_since(&self) -> Option<SystemTime> {
+        self.active_since
     }
 
     #[inline]
@@ -124,18 +419,187 @@ impl Segment {
             Some(val) => val,
             None => return false,
         };
-        return store.size() >= self.config.max_store_bytes
-            || index.size() >= self.config.max_index_bytes;
+        // the index grows on demand, so only its hard ceiling counts
+        if index.size() + index.entry_width() > self.config.max_index_bytes {
+            return true;
+        }
+        let aged = self.active_since.is_some_and(|since| {
+            self.config
+                .max_segment_age
+                // a clock set back makes the segment younger, not aged
+                .is_some_and(|max_age| since.elapsed().is_ok_and(|age| age >= max_age))
+        });
+        if aged {
+            return true;
+        }
+        if store.size() < self.config.max_store_bytes {
+            return false;
+        }
+        self.records >= self.config.min_records_per_segment
+            || self.last_append_bytes > self.config.max_store_bytes
+    }
+
+    /// Writes buffered store bytes and dirty index pages back to their files.
+    pub fn flush(&mut self) -> std::io::Result<()> {
+        if let Some(ref mut store) = self.store {
+            store.flush()?;
+        }
+        self.flush_index()?;
+        Ok(())
+    }
+
+    /// Fsyncs the store and writes dirty index pages back, so the segment's records
+    /// survive a crash.
+    pub fn sync(&mut self) -> std::io::Result<()> {
+        if let Some(ref mut store) = self.store {
+            store.sync()?;
+        }
+        self.flush_index()?;
+        Ok(())
+    }
+
+    pub fn flush_index(&mut self) -> std::io::Result<bool> {
+        match self.index {
+            Some(ref mut index) => index.flush(),
+            None => Ok(false),
+        }
+    }
+
+    pub fn checkpoint(&self) -> Checkpoint {
+        let store_size = self.store.as_ref().map_or(0, Store::size);
+        Checkpoint {
+            store_size,
+            next_offset: self.next_offset,
+            last_index_pos: self.last_index_pos,
+            last_append_bytes: self.last_append_bytes,
+            records: self.records,
+            active_since: self.active_since,
+            last_write: self.last_write,
+        }
+    }
+
+    /// Discards every record appended since `checkpoint` was taken.
+    pub fn rollback(&mut self, checkpoint: Checkpoint) -> std::io::Result<()> {
+        let relative = checkpoint.next_offset - self.base_offset;
+        let (store, index) = match self.get_store_and_index_mut() {
+            Some(val) => val,
+            None => return Err(LogError::Closed.into()),
+        };
+        index.truncate(relative)?;
+        store.truncate(checkpoint.store_size)?;
+        self.next_offset = checkpoint.next_offset;
+        self.last_index_pos = checkpoint.last_index_pos;
+        self.last_append_bytes = checkpoint.last_append_bytes;
+        self.records = checkpoint.records;
+        self.active_since = checkpoint.active_since;
+        self.last_write = checkpoint.last_write;
+        Ok(())
+    }
+
+    /// Writes `segments`, adjacent and in offset order, out as the store and index of one
+    /// segment based at the first one's base offset, syncing both files.
+    ///
+    /// The stores are concatenated and every index entry is kept, shifted to the record's
+    /// place in the merged store.
+    pub fn write_merged(
+        segments: &mut [Segment],
+        store_path: &Path,
+        index_path: &Path,
+        c: &Config,
+    ) -> std::io::Result<()> {
+        let base_offset = match segments.first() {
+            Some(segment) => segment.base_offset,
+            None => return Ok(()),
+        };
+        let index_bytes: u64 = segments
+            .iter()
+            .map(|segment| segment.index.as_ref().map_or(0, |index| index.size()))
+            .sum();
+        if index_bytes > c.max_index_bytes {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!(
+                    "merged index needs {} bytes, more than the max of {}",
+                    index_bytes, c.max_index_bytes
+                ),
+            ));
+        }
+
+        let create = |path: &Path| {
+            new_file_options()
+                .read(true)
+                .write(true)
+                .create(true)
+                .truncate(true)
+                .open(path)
+        };
+        let mut store_out = create(store_path)?;
+        let mut index_out = Index::new(
+            create(index_path)?,
+            Config {
+                read_only: false,
+                validate_on_open: false,
+                ..c.clone()
+            },
+            index_path,
+        )?;
+        let mut store_pos = 0;
+        for segment in segments.iter_mut() {
+            let (store, index) = match (segment.store.as_mut(), segment.index.as_ref()) {
+                (Some(store), Some(index)) => (store, index),
+                _ => return Err(LogError::Closed.into()),
+            };
+            let shift = segment.base_offset - base_offset;
+            for entry in 0..index.size() / index.entry_width() {
+                let (offset, pos) = index.read(entry as i64)?;
+                index_out.write(offset + shift, pos + store_pos)?;
+            }
+
+            store.flush()?;
+            let mut store_in = File::open(&segment.store_name)?.take(store.size());
+            std::io::copy(&mut store_in, &mut store_out)?;
+            store_pos += store.size();
+        }
+
+        store_out.sync_all()?;
+        index_out.close()
     }
 
     pub fn remove(&mut self) -> std::io::Result<()> {
         self.close()?;
+        self.discard_meta()?;
         std::fs::remove_file(&self.index_name)?;
         std::fs::remove_file(&self.store_name)?;
+        if self.config.sync_dir {
+            if let Some(dir) = self.store_name.parent() {
+                sync_dir(dir)?;
+            }
+        }
         Ok(())
     }
 
+    #[inline]
+    pub fn is_closed(&self) -> bool {
+        self.store.is_none() || self.index.is_none()
+    }
+
+    /// Reopens the files of a closed segment so it can be read and appended to again.
+    pub fn reopen(&mut self) -> std::io::Result<()> {
+        if !self.is_closed() {
+            return Ok(());
+        }
+        self.close()?;
+        let (store, index) =
+            open_store_and_index(&self.store_name, &self.index_name, &self.config)?;
+        self.store = Some(store);
+        self.index = Some(index);
+        self.discard_meta()
+    }
+
+    /// Closes the store and index, then records the segment's offsets in its sidecar
+    /// for the next `Segment::new` to start from.
     pub fn close(&mut self) -> std::io::Result<()> {
+        let was_open = !self.is_closed();
         if let Some(index) = self.index.take() {
             index.close()?;
         }
@@ -144,8 +608,165 @@ impl Segment {
             store.close()?;
         }
 
+        if was_open && !self.config.read_only {
+            let meta = Meta {
+                next_offset: self.next_offset,
+                last_write: self.last_write,
+                first_write: self.active_since,
+            };
+            write_meta(&self.meta_name, self.base_offset, meta, &self.config)?;
+        }
         Ok(())
     }
+
+    /// Writes the sidecar of the segment `write_merged` made out of `segments`, closed and
+    /// in offset order, so it opens with their first and last writes rather than the times
+    /// of the merged files. Call once the merged files have replaced the first segment's.
+    pub fn write_merged_meta(segments: &[Segment]) -> std::io::Result<()> {
+        let (first, last) = match (segments.first(), segments.last()) {
+            (Some(first), Some(last)) => (first, last),
+            _ => return Ok(()),
+        };
+        let meta = Meta {
+            next_offset: last.next_offset,
+            last_write: segments.iter().filter_map(|seg| seg.last_write).max(),
+            first_write: segments.iter().filter_map(|seg| seg.active_since).min(),
+        };
+        write_meta(&first.meta_name, first.base_offset, meta, &first.config)
+    }
+}
+
+#[cfg(test)]
+thread_local! {
+    // directories synced by this thread, for tests to check
+    pub(crate) static DIR_SYNCS: std::cell::RefCell<Vec<PathBuf>> = const { std::cell::RefCell::new(Vec::new()) };
+}
+
+/// Fsyncs a directory so the files created or unlinked in it are durable.
+pub fn sync_dir(dir: &Path) -> std::io::Result<()> {
+    File::open(dir)?.sync_all()?;
+    #[cfg(test)]
+    DIR_SYNCS.with(|syncs| syncs.borrow_mut().push(dir.to_path_buf()));
+    Ok(())
+}
+
+// what a segment's sidecar recorded, if it has a whole one for this base
+fn write_meta(path: &Path, base_offset: u64, meta: Meta, c: &Config) -> std::io::Result<()> {
+    let mut buf = [0u8; META_WIDTH];
+    let order = c.byte_order;
+    order.write_u64(&mut buf[..8], base_offset);
+    order.write_u64(&mut buf[8..16], meta.next_offset);
+    let millis = |at: Option<SystemTime>| {
+        at.and_then(|at| at.duration_since(SystemTime::UNIX_EPOCH).ok())
+            .map_or(0, |since| since.as_millis() as u64)
+    };
+    order.write_u64(&mut buf[16..24], millis(meta.last_write));
+    order.write_u64(&mut buf[24..], millis(meta.first_write));
+    std::fs::write(path, buf)
+}
+
+fn read_meta(path: &Path, base_offset: u64, c: &Config) -> std::io::Result<Option<Meta>> {
+    let buf = match std::fs::read(path) {
+        Ok(buf) => buf,
+        Err(err) if err.kind() == ErrorKind::NotFound => return Ok(None),
+        Err(err) => return Err(err),
+    };
+    if !matches!(buf.len(), META_WIDTH | LEGACY_META_WIDTH)
+        || c.byte_order.read_u64(&buf[..8]) != base_offset
+    {
+        // torn by a crash mid-write, or not this segment's; the index still knows
+        return Ok(None);
+    }
+    let time =
+        |millis: u64| (millis > 0).then(|| SystemTime::UNIX_EPOCH + Duration::from_millis(millis));
+    Ok(Some(Meta {
+        next_offset: c.byte_order.read_u64(&buf[8..16]),
+        last_write: time(c.byte_order.read_u64(&buf[16..24])),
+        first_write: match buf.len() {
+            META_WIDTH => time(c.byte_order.read_u64(&buf[24..])),
+            _ => None,
+        },
+    }))
+}
+
+// cuts a record the store only holds part of, as a crash mid-append leaves it, along
+// with the index entries at or past the cut; returns whether anything was cut
+fn recover_torn(store: &mut Store, index: &mut Index) -> std::io::Result<bool> {
+    let entries = (index.size() / index.entry_width()) as i64;
+    // scan from the last entry whose record starts inside the store
+    let mut pos = 0;
+    for i in (0..entries).rev() {
+        let (_, entry_pos) = index.read(i)?;
+        if entry_pos < store.size() {
+            pos = entry_pos;
+            break;
+        }
+    }
+    let mut end = pos;
+    while end < store.size() {
+        match store.read_frame(end) {
+            Ok((_, next)) => end = next,
+            Err(err) if err.kind() == ErrorKind::UnexpectedEof => break,
+            Err(err) => return Err(err),
+        }
+    }
+
+    // an append writes the index entry before the record, so it may outlive it
+    let mut cut = None;
+    for i in (0..entries).rev() {
+        let (offset, entry_pos) = index.read(i)?;
+        if entry_pos < end {
+            break;
+        }
+        cut = Some(offset);
+    }
+    if end == store.size() && cut.is_none() {
+        return Ok(false);
+    }
+    if end < store.size() {
+        store.truncate(end)?;
+    }
+    if let Some(offset) = cut {
+        index.truncate(offset)?;
+    }
+    Ok(true)
+}
+
+fn open_store_and_index(
+    store_path: &Path,
+    index_path: &Path,
+    c: &Config,
+) -> std::io::Result<(Store, Index)> {
+    let store_file = open_segment_file(store_path, c)?;
+    let store = Store::new(store_file, c.clone())?;
+    let index_file = open_segment_file(index_path, c)?;
+    let index = Index::new(index_file, c.clone(), index_path)?;
+    Ok((store, index))
+}
+
+// files are created rw-r--r-- on unix
+#[cfg(unix)]
+fn new_file_options() -> OpenOptions {
+    let mut options = OpenOptions::new();
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o644);
+    options
+}
+
+// windows has no mode bits to set
+#[cfg(not(unix))]
+fn new_file_options() -> OpenOptions {
+    OpenOptions::new()
+}
+
+fn open_segment_file(path: &Path, c: &Config) -> std::io::Result<File> {
+    if c.read_only {
+        return OpenOptions::new().read(true).open(path);
+    }
+    new_file_options()
+        .append(true)
+        .read(true)
+        .create(true)
+        .open(path)
 }
 
 impl Read for Segment {
@@ -160,7 +781,7 @@ impl Read for Segment {
 #[cfg(test)]
 mod tests {
 
-    use crate::log::index::ENTWIDTH;
+    use crate::log::{index::ENTWIDTH, store::HEADER_WIDTH};
 
     use super::*;
 
@@ -175,16 +796,18 @@ mod tests {
             offset: 0,
         };
 
-        let mut c = Config::default();
-        c.max_store_bytes = 1024;
-        c.max_index_bytes = ENTWIDTH as u64 * 3;
+        let mut c = Config {
+            max_store_bytes: 1024,
+            max_index_bytes: ENTWIDTH as u64 * 3,
+            ..Default::default()
+        };
 
-        let mut s = Segment::new(dir.as_ref(), 16, c).unwrap();
+        let mut s = Segment::new(dir.as_ref(), 16, c.clone()).unwrap();
         assert_eq!(16u64, s.next_offset);
         assert!(!s.is_maxed());
 
         for i in 0u64..3 {
-            let offset = s.append(want.clone()).unwrap().unwrap();
+            let offset = s.append(want.clone()).unwrap();
             assert_eq!(16 + i, offset);
             let got = s.read_at_offset(offset).unwrap().unwrap();
             assert_eq!(want.value, got.value);
@@ -199,13 +822,312 @@ mod tests {
         c.max_store_bytes = want.value.len() as u64 * 3;
         c.max_index_bytes = 1024;
 
-        let mut s = Segment::new(dir.as_ref(), 16, c).unwrap();
+        let mut s = Segment::new(dir.as_ref(), 16, c.clone()).unwrap();
         // maxed store
         assert!(s.is_maxed());
 
         s.remove().unwrap();
 
-        let s = Segment::new(dir.as_ref(), 16, c).unwrap();
+        let s = Segment::new(dir.as_ref(), 16, c.clone()).unwrap();
+        assert!(!s.is_maxed());
+    }
+
+    #[test]
+    fn test_segment_max_age() {
+        let dir = tempfile::Builder::new()
+            .prefix("segment-age-test")
+            .tempdir()
+            .unwrap();
+        let c = Config {
+            max_store_bytes: 1024,
+            max_index_bytes: 1024,
+            max_segment_age: Some(std::time::Duration::from_millis(30)),
+            ..Default::default()
+        };
+
+        let record = Record {
+            value: b"hello".to_vec(),
+            offset: 0,
+        };
+
+        let mut s = Segment::new(dir.as_ref(), 0, c.clone()).unwrap();
+        std::thread::sleep(std::time::Duration::from_millis(40));
+        // the age counts from the first record, not from when the segment was created
+        s.append(record.clone()).unwrap();
+        assert!(!s.is_maxed());
+        std::thread::sleep(std::time::Duration::from_millis(40));
+        assert!(s.is_maxed());
+        s.close().unwrap();
+
+        // the age survives reopening, through the sidecar or the store file without it
+        let mut s = Segment::new(dir.as_ref(), 0, c.clone()).unwrap();
+        assert!(s.is_maxed());
+        s.close().unwrap();
+        let meta = dir.path().join(format!("0.{}", META_EXTENSION));
+        std::fs::remove_file(meta).unwrap();
+        let mut s = Segment::new(dir.as_ref(), 0, c.clone()).unwrap();
+        assert!(s.is_maxed());
+        s.remove().unwrap();
+
+        // a fresh segment's age is recorded at close and read back on open
+        let mut s = Segment::new(dir.as_ref(), 0, c.clone()).unwrap();
+        s.append(record).unwrap();
+        s.close().unwrap();
+        let s = Segment::new(dir.as_ref(), 0, c).unwrap();
+        assert!(!s.is_maxed());
+    }
+
+    #[test]
+    fn test_segment_sparse_index() {
+        let dir = tempfile::Builder::new()
+            .prefix("segment-sparse-test")
+            .tempdir()
+            .unwrap();
+        let want = Record {
+            value: Vec::from(b"hello world"),
+            offset: 0,
+        };
+
+        let mut buf: Vec<u8> = Vec::new();
+        want.encode(&mut buf).unwrap();
+        let width = HEADER_WIDTH + buf.len() as u64;
+
+        // one index entry for every three records
+        let c = Config {
+            max_store_bytes: 1024,
+            max_index_bytes: 1024,
+            index_interval_bytes: width * 3,
+            ..Default::default()
+        };
+
+        let mut s = Segment::new(dir.as_ref(), 16, c.clone()).unwrap();
+        for i in 0u64..10 {
+            let offset = s.append(want.clone()).unwrap();
+            assert_eq!(16 + i, offset);
+        }
+        assert_eq!(4 * ENTWIDTH as u64, s.index.as_ref().unwrap().size());
+
+        for offset in 16u64..26 {
+            let got = s.read_at_offset(offset).unwrap().unwrap();
+            assert_eq!(offset, got.offset);
+            assert_eq!(want.value, got.value);
+        }
+        let err = s.read_at_offset(26).map_err(|e| e.kind());
+        assert_eq!(err, Err(std::io::ErrorKind::UnexpectedEof));
+
+        s.append(want.clone()).unwrap();
+        s.close().unwrap();
+
+        // the records after the last index entry are recovered from the store
+        let mut s = Segment::new(dir.as_ref(), 16, c.clone()).unwrap();
+        assert_eq!(27, s.next_offset);
+        let got = s.read_at_offset(26).unwrap().unwrap();
+        assert_eq!(26, got.offset);
+        assert_eq!(27, s.append(want.clone()).unwrap());
+        assert_eq!(27, s.read_at_offset(27).unwrap().unwrap().offset);
+    }
+
+    #[test]
+    fn test_segment_min_records() {
+        let dir = tempfile::Builder::new()
+            .prefix("segment-min-records-test")
+            .tempdir()
+            .unwrap();
+        let small = Record {
+            value: Vec::from(b"hello world"),
+            offset: 0,
+        };
+        let mut buf: Vec<u8> = Vec::new();
+        small.encode(&mut buf).unwrap();
+        let width = HEADER_WIDTH + buf.len() as u64;
+
+        // two records fill the store
+        let c = Config {
+            max_store_bytes: width + 4,
+            max_index_bytes: 1024,
+            min_records_per_segment: 3,
+            ..Default::default()
+        };
+        let mut s = Segment::new(dir.as_ref(), 0, c.clone()).unwrap();
+        s.append(small.clone()).unwrap();
+        s.append(small.clone()).unwrap();
+        assert!(!s.is_maxed());
+        s.append(small.clone()).unwrap();
+        assert!(s.is_maxed());
+
+        // a record over the max rolls the segment regardless
+        let big = Record {
+            value: vec![0; 2 * width as usize],
+            offset: 0,
+        };
+        let mut s = Segment::new(dir.as_ref(), 3, c.clone()).unwrap();
+        s.append(big).unwrap();
+        assert!(s.is_maxed());
+        s.close().unwrap();
+
+        // gaps in the offsets don't count as records, before or after a reopen
+        let mut s = Segment::new(dir.as_ref(), 10, c.clone()).unwrap();
+        s.set_offset_assigner(Arc::new(Gap(10)));
+        s.append(small.clone()).unwrap();
+        s.append(small.clone()).unwrap();
+        assert_eq!(30, s.next_offset);
+        assert!(!s.is_maxed());
+        s.close().unwrap();
+        let mut s = Segment::new(dir.as_ref(), 10, c).unwrap();
         assert!(!s.is_maxed());
+        s.append(small).unwrap();
+        assert!(s.is_maxed());
+    }
+
+    #[derive(Debug)]
+    struct Gap(u64);
+
+    impl OffsetAssigner for Gap {
+        fn assign(&self, next_offset: u64) -> u64 {
+            next_offset + self.0 - 1
+        }
+    }
+
+    #[test]
+    fn test_segment_meta() {
+        let dir = tempfile::Builder::new()
+            .prefix("segment-meta-test")
+            .tempdir()
+            .unwrap();
+        let c = Config {
+            max_store_bytes: 1024,
+            max_index_bytes: 1024,
+            ..Default::default()
+        };
+        let meta = dir.path().join("4.meta");
+        let mut s = Segment::new(dir.as_ref(), 4, c.clone()).unwrap();
+        for _ in 0..3 {
+            let record = Record {
+                value: b"hello".to_vec(),
+                offset: 0,
+            };
+            s.append(record).unwrap();
+        }
+        s.close().unwrap();
+        assert!(meta.exists());
+
+        // a torn append left the store ahead of an empty index
+        std::fs::write(dir.path().join("4.index"), []).unwrap();
+        let mut s = Segment::new(dir.as_ref(), 4, c.clone()).unwrap();
+        assert_eq!(7, s.next_offset);
+        // open segments go without a sidecar until they close again
+        assert!(!meta.exists());
+        s.close().unwrap();
+
+        // without the sidecar the offsets come from the index
+        std::fs::remove_file(&meta).unwrap();
+        let s = Segment::new(dir.as_ref(), 4, c).unwrap();
+        assert_eq!(4, s.next_offset);
+    }
+
+    #[test]
+    fn test_read_all_from_store() {
+        let dir = tempfile::Builder::new()
+            .prefix("segment-store-only-test")
+            .tempdir()
+            .unwrap();
+        let c = Config {
+            max_store_bytes: 1024,
+            max_index_bytes: 1024,
+            ..Default::default()
+        };
+        let mut s = Segment::new(dir.as_ref(), 8, c.clone()).unwrap();
+        for i in 0..4u8 {
+            let record = Record {
+                value: vec![i; 5],
+                offset: 0,
+            };
+            s.append(record).unwrap();
+        }
+        s.close().unwrap();
+
+        // lose the index and half-write another record
+        std::fs::remove_file(dir.path().join("8.index")).unwrap();
+        let mut store = OpenOptions::new()
+            .append(true)
+            .open(dir.path().join("8.store"))
+            .unwrap();
+        std::io::Write::write_all(&mut store, &[0, 0, 0, 0, 0, 0, 0, 64, 1, 2]).unwrap();
+
+        let mut s = Segment::new(dir.as_ref(), 8, c).unwrap();
+        let records = s.read_all_from_store().unwrap();
+        assert_eq!(4, records.len());
+        for (i, record) in records.iter().enumerate() {
+            assert_eq!(8 + i as u64, record.offset);
+            assert_eq!(vec![i as u8; 5], record.value);
+        }
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_failed_append_leaves_no_entry() {
+        let dir = tempfile::Builder::new()
+            .prefix("segment-failed-append-test")
+            .tempdir()
+            .unwrap();
+        // every write to the store fails with ENOSPC
+        std::os::unix::fs::symlink("/dev/full", dir.path().join("0.store")).unwrap();
+        let c = Config {
+            max_store_bytes: 1 << 20,
+            max_index_bytes: 1024,
+            ..Default::default()
+        };
+        let mut s = Segment::new(dir.as_ref(), 0, c).unwrap();
+        // larger than the store's write buffer, so the write reaches the file
+        let record = Record {
+            value: vec![b'x'; 64 * 1024],
+            offset: 0,
+        };
+        for _ in 0..2 {
+            s.append(record.clone()).unwrap_err();
+            assert_eq!(0, s.next_offset);
+            assert_eq!(0, s.index.as_ref().unwrap().size());
+        }
+    }
+
+    #[test]
+    fn test_closed_segment_errors() {
+        let dir = tempfile::Builder::new()
+            .prefix("segment-closed-test")
+            .tempdir()
+            .unwrap();
+        let c = Config {
+            max_store_bytes: 1024,
+            max_index_bytes: 1024,
+            ..Default::default()
+        };
+        let mut s = Segment::new(dir.as_ref(), 0, c).unwrap();
+        let checkpoint = s.checkpoint();
+        s.close().unwrap();
+
+        // every path reports the same error, so the server maps it the same way
+        let record = Record {
+            value: b"hello".to_vec(),
+            offset: 0,
+        };
+        let errs = [
+            s.append(record).unwrap_err(),
+            s.position_of(0).unwrap_err(),
+            s.read_all_from_store().unwrap_err(),
+            s.record_hashes(0).unwrap_err(),
+            s.rollback(checkpoint).unwrap_err(),
+        ];
+        for err in errs {
+            assert!(matches!(
+                err.get_ref().and_then(|e| e.downcast_ref::<LogError>()),
+                Some(LogError::Closed)
+            ));
+        }
+        // a read names the segment, so the log can tell which one to reopen
+        let err = s.read_at_offset(0).unwrap_err();
+        assert_eq!(
+            Some(&LogError::SegmentClosed { base_offset: 0 }),
+            err.get_ref().and_then(|e| e.downcast_ref::<LogError>())
+        );
     }
 }
@@ -1,12 +1,28 @@
 use std::{
+    borrow::Cow,
     fs::File,
-    io::{BufWriter, Read, Write},
-    os::unix::prelude::FileExt,
+    io::{BufWriter, Read, Seek, SeekFrom, Write},
 };
 
-use byteorder::{BigEndian, ByteOrder, WriteBytesExt};
+use super::{
+    config::{ByteOrderKind, Config},
+    transform,
+};
 
 pub const LEN_WIDTH: u64 = 8;
+pub const CRC_WIDTH: u64 = 4;
+/// Bytes in front of each payload written by `append`: its length prefix and checksum.
+pub const HEADER_WIDTH: u64 = LEN_WIDTH + CRC_WIDTH;
+
+// A frame's length prefix carries its layout version in the top byte, so stores written
+// before checksums, whose prefixes are plain lengths, keep reading as version 0 frames
+// alongside the checksummed frames appended after them.
+//
+// version 0: len (u64) | payload
+// version 1: version << 56 | len (u64) | crc32c(payload) (u32) | payload
+const FRAME_VERSION: u64 = 1;
+const VERSION_SHIFT: u32 = 56;
+const LEN_MASK: u64 = (1 << VERSION_SHIFT) - 1;
 
 #[derive(Debug)]
 pub struct Store {
@@ -14,10 +30,15 @@ pub struct Store {
     writer: BufWriter<File>,
     size: u64,
     offset: usize,
+    config: Config,
+    read_buf: Vec<u8>,
+    // store position of `read_buf[0]` and the number of valid bytes buffered from it
+    buf_pos: u64,
+    buf_len: usize,
 }
 
 impl Store {
-    pub fn new(file: File) -> std::io::Result<Self> {
+    pub fn new(file: File, c: Config) -> std::io::Result<Self> {
         let fi = file.metadata()?;
         let size = fi.len();
         let write_file = file.try_clone()?;
@@ -27,6 +48,10 @@ impl Store {
             writer,
             size,
             offset: 0,
+            read_buf: vec![0; c.read_buffer_bytes as usize],
+            config: c,
+            buf_pos: 0,
+            buf_len: 0,
         };
 
         Ok(store)
@@ -35,33 +60,259 @@ impl Store {
     pub fn try_clone(&mut self) -> std::io::Result<Self> {
         self.writer.flush()?;
         let clone_file = self.file.try_clone()?;
-        Self::new(clone_file)
+        Self::new(clone_file, self.config.clone())
     }
 
     pub fn append(&mut self, p: &[u8]) -> std::io::Result<(u64, u64)> {
+        if self.config.read_only {
+            return Err(std::io::Error::from(std::io::ErrorKind::PermissionDenied));
+        }
+        let p = self.encode(p)?;
         let pos = self.size;
-        let len = p.len() as u64;
-        self.writer.write_u64::<BigEndian>(len)?;
-        self.writer.write_all(p)?;
-        let w = len + LEN_WIDTH;
+        self.writer
+            .write_all(&frame_header(&p, self.config.byte_order))?;
+        self.writer.write_all(&p)?;
+        let w = p.len() as u64 + HEADER_WIDTH;
         self.size += w;
         Ok((w, pos))
     }
 
-    pub fn read_at_offset(&mut self, pos: u64) -> std::io::Result<Vec<u8>> {
+    /// Appends every record with a single write, returning the `(bytes_written, pos)` of
+    /// each, as one `append` call per record would have.
+    ///
+    /// The frames are put together in memory first, so the writer's buffer is flushed at
+    /// most once. Nothing is written if any record can't be framed.
+    pub fn append_batch(&mut self, records: &[&[u8]]) -> std::io::Result<Vec<(u64, u64)>> {
+        if self.config.read_only {
+            return Err(std::io::Error::from(std::io::ErrorKind::PermissionDenied));
+        }
+        let mut buf = Vec::new();
+        let mut appended = Vec::with_capacity(records.len());
+        for p in records {
+            let p = self.encode(p)?;
+            let pos = self.size + buf.len() as u64;
+            buf.extend_from_slice(&frame_header(&p, self.config.byte_order));
+            buf.extend_from_slice(&p);
+            appended.push((p.len() as u64 + HEADER_WIDTH, pos));
+        }
+        self.writer.write_all(&buf)?;
+        self.size += buf.len() as u64;
+        Ok(appended)
+    }
+
+    // runs `p` through the write transforms, checking it still fits in a frame
+    fn encode<'a>(&self, p: &'a [u8]) -> std::io::Result<Cow<'a, [u8]>> {
+        let p = if self.config.transforms.is_empty() {
+            Cow::Borrowed(p)
+        } else {
+            Cow::Owned(transform::apply_write(&self.config.transforms, p))
+        };
+        if p.len() as u64 > LEN_MASK {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!("record of {} bytes is too large", p.len()),
+            ));
+        }
+        Ok(p)
+    }
+
+    /// Appends a buffer of already framed payloads in one write, e.g. from `encode_frame`.
+    ///
+    /// `frame_positions` holds each frame's `(start, len)` within `buf`, where `len` is the
+    /// payload length after the header. The frames must tile `buf` exactly. Returns the
+    /// `(bytes_written, pos)` of every frame, as `append` would have. Checksums are only
+    /// verified when the frames are read. Since the frames bypass `Config::transforms`, a
+    /// store with a transform pipeline refuses them.
+    pub fn append_raw_frames(
+        &mut self,
+        buf: &[u8],
+        frame_positions: &[(u64, u64)],
+    ) -> std::io::Result<Vec<(u64, u64)>> {
+        if self.config.read_only {
+            return Err(std::io::Error::from(std::io::ErrorKind::PermissionDenied));
+        }
+
+        let invalid = |msg: String| std::io::Error::new(std::io::ErrorKind::InvalidInput, msg);
+        if !self.config.transforms.is_empty() {
+            return Err(invalid(
+                "raw frames can't be appended to a store with transforms".to_string(),
+            ));
+        }
+        let order = self.config.byte_order;
+        let mut end = 0u64;
+        for (i, &(start, len)) in frame_positions.iter().enumerate() {
+            if start != end {
+                return Err(invalid(format!(
+                    "frame {} starts at {}, want {}",
+                    i, start, end
+                )));
+            }
+            if start + LEN_WIDTH > buf.len() as u64 {
+                return Err(invalid(format!("frame {} overruns the buffer", i)));
+            }
+            let prefix = order.read_u64(&buf[start as usize..(start + LEN_WIDTH) as usize]);
+            let payload = match header_width(prefix) {
+                Some(width) => start + width,
+                None => return Err(invalid(format!("frame {} has an unknown version", i))),
+            };
+            if payload + len > buf.len() as u64 {
+                return Err(invalid(format!("frame {} overruns the buffer", i)));
+            }
+            let prefix = prefix & LEN_MASK;
+            if prefix != len {
+                return Err(invalid(format!(
+                    "frame {} is prefixed with length {}, want {}",
+                    i, prefix, len
+                )));
+            }
+            end = payload + len;
+        }
+        if end != buf.len() as u64 {
+            return Err(invalid(format!(
+                "frames cover {} of {} bytes",
+                end,
+                buf.len()
+            )));
+        }
+
+        self.writer.write_all(buf)?;
+        let base = self.size;
+        self.size += buf.len() as u64;
+        let ends = frame_positions
+            .iter()
+            .skip(1)
+            .map(|&(start, _)| start)
+            .chain(std::iter::once(end));
+        Ok(frame_positions
+            .iter()
+            .zip(ends)
+            .map(|(&(start, _), end)| (end - start, base + start))
+            .collect())
+    }
+
+    pub fn flush(&mut self) -> std::io::Result<()> {
+        self.writer.flush()
+    }
+
+    /// Flushes the write buffer and fsyncs the file, so the records appended so far
+    /// survive a crash.
+    pub fn sync(&mut self) -> std::io::Result<()> {
+        self.writer.flush()?;
+        self.file.sync_all()
+    }
+
+    /// Cuts the store back to `size` bytes.
+    pub fn truncate(&mut self, size: u64) -> std::io::Result<()> {
+        if size > self.size {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!("can not truncate store of {} bytes to {}", self.size, size),
+            ));
+        }
         self.writer.flush()?;
-        let mut size = [0u8; 8];
-        self.file.read_at(&mut size, pos)?;
-        let size = BigEndian::read_u64(&size);
+        self.file.set_len(size)?;
+        // without O_APPEND the next write would land at the old end
+        self.writer.get_mut().seek(SeekFrom::Start(size))?;
+        self.size = size;
+        self.buf_len = 0;
+        self.offset = self.offset.min(size as usize);
+        Ok(())
+    }
+
+    pub fn read_at_offset(&mut self, pos: u64) -> std::io::Result<Vec<u8>> {
+        Ok(self.read_frame(pos)?.0)
+    }
+
+    /// Iterates over every record in the store from the start, without the index.
+    ///
+    /// A trailing record cut short, e.g. by a crash mid-append, ends the iteration.
+    pub fn records(&mut self) -> Records<'_> {
+        Records { iter: self.iter() }
+    }
+
+    /// Iterates over every record in the store from the start along with its position,
+    /// e.g. to rebuild a lost index.
+    ///
+    /// A trailing record cut short is reported as an `ErrorKind::UnexpectedEof` error, and
+    /// the iteration ends after the first error. Takes `&mut self` because reading flushes
+    /// the write buffer.
+    pub fn iter(&mut self) -> Iter<'_> {
+        Iter {
+            store: self,
+            pos: 0,
+            done: false,
+        }
+    }
+
+    /// Reads the record at `pos`, also returning the position of the record after it.
+    ///
+    /// Fails with `ErrorKind::InvalidData` if the record doesn't match its checksum.
+    pub fn read_frame(&mut self, pos: u64) -> std::io::Result<(Vec<u8>, u64)> {
+        let mut prefix = [0u8; LEN_WIDTH as usize];
+        self.read_exact_at_checked(&mut prefix, pos)?;
+        let prefix = self.config.byte_order.read_u64(&prefix);
+        let width = header_width(prefix).ok_or_else(|| {
+            std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!(
+                    "record at {} has unknown version {}",
+                    pos,
+                    prefix >> VERSION_SHIFT
+                ),
+            )
+        })?;
+        let size = prefix & LEN_MASK;
+        // check the length before trusting it with an allocation
+        if (pos + width).saturating_add(size) > self.size {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::UnexpectedEof,
+                format!("record at {} runs past the end of the store", pos),
+            ));
+        }
         let mut buf: Vec<u8> = vec![0; size as usize];
-        self.file.read_exact_at(&mut buf, pos + LEN_WIDTH)?;
-        Ok(buf)
+        self.read_exact_at_checked(&mut buf, pos + width)?;
+        if width == HEADER_WIDTH {
+            let mut crc = [0u8; CRC_WIDTH as usize];
+            self.read_exact_at_checked(&mut crc, pos + LEN_WIDTH)?;
+            if self.config.byte_order.read_u32(&crc) != crc32c::crc32c(&buf) {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidData,
+                    format!("record at {} fails its checksum", pos),
+                ));
+            }
+        }
+        let next = pos + width + size;
+        if self.config.transforms.is_empty() {
+            return Ok((buf, next));
+        }
+        Ok((transform::apply_read(&self.config.transforms, buf), next))
     }
 
+    /// Reads into `p` from `offset`, stopping at the end of the stored data.
     pub fn read_at(&mut self, p: &mut [u8], offset: u64) -> std::io::Result<usize> {
         self.writer.flush()?;
-        self.file.read_at(p, offset)?;
-        Ok(p.len())
+        if offset >= self.size {
+            return Ok(0);
+        }
+        let len = p.len().min((self.size - offset) as usize);
+        read_file_at(&self.file, &mut p[..len], offset)
+    }
+
+    /// Fills `p` from `offset`, failing if the range isn't entirely stored data.
+    pub fn read_exact_at_checked(&mut self, p: &mut [u8], offset: u64) -> std::io::Result<()> {
+        if offset.saturating_add(p.len() as u64) > self.size {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::UnexpectedEof,
+                format!(
+                    "range {}..{} is past the end of the store at {}",
+                    offset,
+                    offset.saturating_add(p.len() as u64),
+                    self.size
+                ),
+            ));
+        }
+        self.writer.flush()?;
+        read_file_exact_at(&self.file, p, offset)
     }
 
     pub fn close(mut self) -> std::io::Result<()> {
@@ -72,7 +323,116 @@ impl Store {
 
     #[inline]
     pub fn size(&self) -> u64 {
-        return self.size;
+        self.size
+    }
+
+    /// Bytes already written through to the file, leaving out those still buffered.
+    pub fn flushed_size(&self) -> u64 {
+        self.size - self.writer.buffer().len() as u64
+    }
+
+    fn fill_read_buf(&mut self, pos: u64) -> std::io::Result<()> {
+        self.writer.flush()?;
+        let want = self.read_buf.len().min((self.size - pos) as usize);
+        let n = read_file_at(&self.file, &mut self.read_buf[..want], pos)?;
+        self.buf_pos = pos;
+        self.buf_len = n;
+        Ok(())
+    }
+}
+
+// positional read that works on both unix and windows
+#[cfg(unix)]
+fn read_file_at(file: &File, buf: &mut [u8], offset: u64) -> std::io::Result<usize> {
+    std::os::unix::fs::FileExt::read_at(file, buf, offset)
+}
+
+// unlike pread this moves the file cursor, which is harmless since appends always go
+// to the end and every read passes its offset
+#[cfg(windows)]
+fn read_file_at(file: &File, buf: &mut [u8], offset: u64) -> std::io::Result<usize> {
+    std::os::windows::fs::FileExt::seek_read(file, buf, offset)
+}
+
+fn read_file_exact_at(file: &File, mut buf: &mut [u8], mut offset: u64) -> std::io::Result<()> {
+    while !buf.is_empty() {
+        match read_file_at(file, buf, offset) {
+            Ok(0) => return Err(std::io::Error::from(std::io::ErrorKind::UnexpectedEof)),
+            Ok(n) => {
+                buf = &mut buf[n..];
+                offset += n as u64;
+            }
+            Err(err) if err.kind() == std::io::ErrorKind::Interrupted => {}
+            Err(err) => return Err(err),
+        }
+    }
+    Ok(())
+}
+
+/// Frames `p` the way `append` writes it, for `append_raw_frames`.
+pub fn encode_frame(p: &[u8], order: ByteOrderKind) -> Vec<u8> {
+    let mut buf = Vec::with_capacity(HEADER_WIDTH as usize + p.len());
+    buf.extend_from_slice(&frame_header(p, order));
+    buf.extend_from_slice(p);
+    buf
+}
+
+fn frame_header(p: &[u8], order: ByteOrderKind) -> [u8; HEADER_WIDTH as usize] {
+    let mut header = [0u8; HEADER_WIDTH as usize];
+    let (prefix, crc) = header.split_at_mut(LEN_WIDTH as usize);
+    order.write_u64(prefix, FRAME_VERSION << VERSION_SHIFT | p.len() as u64);
+    order.write_u32(crc, crc32c::crc32c(p));
+    header
+}
+
+// width of the header in front of the payload of a frame with this length prefix
+fn header_width(prefix: u64) -> Option<u64> {
+    match prefix >> VERSION_SHIFT {
+        0 => Some(LEN_WIDTH),
+        FRAME_VERSION => Some(HEADER_WIDTH),
+        _ => None,
+    }
+}
+
+pub struct Iter<'a> {
+    store: &'a mut Store,
+    pos: u64,
+    done: bool,
+}
+
+impl Iterator for Iter<'_> {
+    type Item = std::io::Result<(u64, Vec<u8>)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done || self.pos >= self.store.size {
+            return None;
+        }
+        match self.store.read_frame(self.pos) {
+            Ok((buf, next)) => {
+                let pos = std::mem::replace(&mut self.pos, next);
+                Some(Ok((pos, buf)))
+            }
+            Err(err) => {
+                self.done = true;
+                Some(Err(err))
+            }
+        }
+    }
+}
+
+pub struct Records<'a> {
+    iter: Iter<'a>,
+}
+
+impl Iterator for Records<'_> {
+    type Item = std::io::Result<Vec<u8>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self.iter.next()? {
+            Ok((_, buf)) => Some(Ok(buf)),
+            Err(err) if err.kind() == std::io::ErrorKind::UnexpectedEof => None,
+            Err(err) => Some(Err(err)),
+        }
     }
 }
 
@@ -81,7 +441,26 @@ impl Read for Store {
         if self.offset >= self.size as usize {
             return Ok(0);
         }
-        let n = self.read_at(buf, self.offset as u64)?;
+        let pos = self.offset as u64;
+        let buffered = pos >= self.buf_pos && pos < self.buf_pos + self.buf_len as u64;
+
+        // requests at least as large as the buffer gain nothing from copying through it
+        if !buffered && buf.len() >= self.read_buf.len() {
+            let n = self.read_at(buf, pos)?;
+            self.offset += n;
+            return Ok(n);
+        }
+
+        if !buffered {
+            self.fill_read_buf(pos)?;
+            if self.buf_len == 0 {
+                return Ok(0);
+            }
+        }
+
+        let start = (pos - self.buf_pos) as usize;
+        let n = buf.len().min(self.buf_len - start);
+        buf[..n].copy_from_slice(&self.read_buf[start..start + n]);
         self.offset += n;
         Ok(n)
     }
@@ -93,8 +472,8 @@ mod tests {
 
     use super::*;
     use tempfile::Builder;
-    const DUMMY_MSG: &'static [u8] = b"hello world";
-    const WIDTH: u64 = DUMMY_MSG.len() as u64 + LEN_WIDTH;
+    const DUMMY_MSG: &[u8] = b"hello world";
+    const WIDTH: u64 = DUMMY_MSG.len() as u64 + HEADER_WIDTH;
 
     fn test_append(s: &mut Store) {
         for i in 1..4u64 {
@@ -119,8 +498,8 @@ mod tests {
             let nbytes = s.read_at(&mut buf, offset).unwrap() as u64;
             assert_eq!(LEN_WIDTH, nbytes);
 
-            offset += nbytes;
-            let size = BigEndian::read_u64(&buf) as usize;
+            offset += nbytes + CRC_WIDTH;
+            let size = (u64::from_be_bytes(buf) & LEN_MASK) as usize;
             let mut buf: Vec<u8> = vec![0; size];
             let nbytes = s.read_at(&mut buf, offset).unwrap();
             assert_eq!(DUMMY_MSG, buf);
@@ -137,12 +516,12 @@ mod tests {
             .tempfile()
             .unwrap();
 
-        let mut s = Store::new(file.reopen().unwrap()).unwrap();
+        let mut s = Store::new(file.reopen().unwrap(), Config::default()).unwrap();
         test_append(&mut s);
         test_read(&mut s);
         test_read_at(&mut s);
 
-        let mut s = Store::new(file.into_file()).unwrap();
+        let mut s = Store::new(file.into_file(), Config::default()).unwrap();
         test_read(&mut s);
     }
 
@@ -155,7 +534,7 @@ mod tests {
             .unwrap();
         let path = file.path().to_owned();
         let path = path.as_path();
-        let mut s = Store::new(file.reopen().unwrap()).unwrap();
+        let mut s = Store::new(file.reopen().unwrap(), Config::default()).unwrap();
         let (_, before_size) = open_file(path).unwrap();
         s.append(DUMMY_MSG).unwrap();
 
@@ -166,6 +545,153 @@ mod tests {
         assert!(after_size > before_size);
     }
 
+    #[test]
+    fn test_store_truncate() {
+        let file = Builder::new()
+            .append(true)
+            .prefix("store-truncate-test")
+            .tempfile()
+            .unwrap();
+        let mut s = Store::new(file.reopen().unwrap(), Config::default()).unwrap();
+        test_append(&mut s);
+        assert!(s.truncate(WIDTH * 4).is_err());
+
+        s.truncate(WIDTH).unwrap();
+        assert_eq!(WIDTH, s.size());
+        assert!(s.read_at_offset(WIDTH).is_err());
+        let (_, pos) = s.append(DUMMY_MSG).unwrap();
+        assert_eq!(WIDTH, pos);
+        s.close().unwrap();
+
+        let mut s = Store::new(file.into_file(), Config::default()).unwrap();
+        assert_eq!(WIDTH * 2, s.size());
+        assert_eq!(DUMMY_MSG, s.read_at_offset(WIDTH).unwrap());
+    }
+
+    #[test]
+    fn test_store_buffered_read() {
+        for read_buffer_bytes in [0u64, 1, 7, 19, 64, 4096] {
+            let file = Builder::new()
+                .append(true)
+                .prefix("store-buffered-read-test")
+                .tempfile()
+                .unwrap();
+            let c = Config {
+                read_buffer_bytes,
+                ..Default::default()
+            };
+
+            let mut s = Store::new(file.reopen().unwrap(), c.clone()).unwrap();
+            test_append(&mut s);
+
+            let want = encode_frame(DUMMY_MSG, ByteOrderKind::BigEndian).repeat(3);
+
+            let mut got: Vec<u8> = Vec::new();
+            s.read_to_end(&mut got).unwrap();
+            assert_eq!(want, got, "read_buffer_bytes: {}", read_buffer_bytes);
+
+            // small reads interleaved with positioned lookups stay in step
+            let mut s = Store::new(file.reopen().unwrap(), c.clone()).unwrap();
+            let mut got: Vec<u8> = Vec::new();
+            let mut chunk = [0u8; 5];
+            loop {
+                let n = s.read(&mut chunk).unwrap();
+                if n == 0 {
+                    break;
+                }
+                got.extend_from_slice(&chunk[..n]);
+                assert_eq!(DUMMY_MSG, s.read_at_offset(WIDTH).unwrap());
+            }
+            assert_eq!(want, got, "read_buffer_bytes: {}", read_buffer_bytes);
+        }
+    }
+
+    #[test]
+    fn test_store_read_at_clamped() {
+        let file = Builder::new()
+            .append(true)
+            .prefix("store-read-at-clamped-test")
+            .tempfile()
+            .unwrap();
+        let mut s = Store::new(file.reopen().unwrap(), Config::default()).unwrap();
+        s.append(DUMMY_MSG).unwrap();
+
+        // straddling the end only returns the stored bytes
+        let mut buf = vec![0xffu8; WIDTH as usize + 10];
+        let n = s.read_at(&mut buf, 4).unwrap();
+        assert_eq!(WIDTH as usize - 4, n);
+        assert_eq!(DUMMY_MSG, &buf[(HEADER_WIDTH as usize - 4)..n]);
+        assert_eq!(0, s.read_at(&mut buf, WIDTH).unwrap());
+        assert_eq!(0, s.read_at(&mut buf, WIDTH + 100).unwrap());
+
+        let err = s.read_exact_at_checked(&mut buf, 4).map_err(|e| e.kind());
+        assert_eq!(err, Err(std::io::ErrorKind::UnexpectedEof));
+        let mut buf = vec![0u8; DUMMY_MSG.len()];
+        s.read_exact_at_checked(&mut buf, HEADER_WIDTH).unwrap();
+        assert_eq!(DUMMY_MSG, buf);
+        let err = s
+            .read_exact_at_checked(&mut buf, HEADER_WIDTH + 1)
+            .map_err(|e| e.kind());
+        assert_eq!(err, Err(std::io::ErrorKind::UnexpectedEof));
+    }
+
+    #[test]
+    fn test_store_append_raw_frames() {
+        let file = Builder::new()
+            .append(true)
+            .prefix("store-raw-frames-test")
+            .tempfile()
+            .unwrap();
+        let mut s = Store::new(file.reopen().unwrap(), Config::default()).unwrap();
+        s.append(DUMMY_MSG).unwrap();
+
+        let msgs: [&[u8]; 3] = [b"first", b"", b"third record"];
+        let mut buf: Vec<u8> = Vec::new();
+        let mut frames: Vec<(u64, u64)> = Vec::new();
+        for msg in msgs.iter() {
+            frames.push((buf.len() as u64, msg.len() as u64));
+            buf.extend_from_slice(&encode_frame(msg, ByteOrderKind::BigEndian));
+        }
+
+        let appended = s.append_raw_frames(&buf, &frames).unwrap();
+        assert_eq!(3, appended.len());
+        assert_eq!((msgs[0].len() as u64 + HEADER_WIDTH, WIDTH), appended[0]);
+        for (msg, (_, pos)) in msgs.iter().zip(appended.iter()) {
+            assert_eq!(*msg, &s.read_at_offset(*pos).unwrap()[..]);
+        }
+        assert_eq!(WIDTH + buf.len() as u64, s.size());
+
+        // a following append lands right after the frames
+        let (_, pos) = s.append(DUMMY_MSG).unwrap();
+        assert_eq!(WIDTH + buf.len() as u64, pos);
+
+        let size = s.size();
+        let bad: Vec<Vec<(u64, u64)>> = vec![
+            vec![(0, 5), (17, 0)],
+            vec![(0, 5), (18, 0), (30, 11)],
+            vec![(0, 6), (17, 0), (29, 12)],
+            vec![(0, 5), (17, 0), (29, 20)],
+        ];
+        for frames in bad {
+            let err = s.append_raw_frames(&buf, &frames).map_err(|e| e.kind());
+            assert_eq!(err, Err(std::io::ErrorKind::InvalidInput));
+        }
+        assert_eq!(size, s.size());
+
+        // frames would skip the pipeline that reads undo, so they're refused
+        let c = Config {
+            transforms: std::sync::Arc::new(vec![Box::new(
+                crate::log::transform::tests::RunLength,
+            )]),
+            ..Default::default()
+        };
+        let mut s = Store::new(file.reopen().unwrap(), c).unwrap();
+        let size = s.size();
+        let err = s.append_raw_frames(&buf, &frames).map_err(|e| e.kind());
+        assert_eq!(err, Err(std::io::ErrorKind::InvalidInput));
+        assert_eq!(size, s.size());
+    }
+
     fn open_file(path: &Path) -> std::io::Result<(File, u64)> {
         let file = OpenOptions::new()
             .read(true)
@@ -175,4 +701,196 @@ mod tests {
         let fi = file.metadata()?;
         Ok((file, fi.len()))
     }
+
+    #[test]
+    fn test_store_transforms() {
+        use crate::log::transform::tests::{RunLength, Xor};
+
+        let file = Builder::new()
+            .append(true)
+            .prefix("store-transforms-test")
+            .tempfile()
+            .unwrap();
+        let c = Config {
+            transforms: std::sync::Arc::new(vec![
+                Box::new(RunLength),
+                Box::new(Xor(b"secret".to_vec())),
+            ]),
+            ..Default::default()
+        };
+        let value = b"aaaaaaaaaaaaaaaaaaaabcccccccccccc".to_vec();
+
+        let mut s = Store::new(file.reopen().unwrap(), c.clone()).unwrap();
+        let (first, _) = s.append(&value).unwrap();
+        let (_, pos) = s.append(b"xyz").unwrap();
+        assert!(first < LEN_WIDTH + value.len() as u64);
+        assert_eq!(value, s.read_at_offset(0).unwrap());
+        assert_eq!((b"xyz".to_vec(), s.size()), s.read_frame(pos).unwrap());
+
+        // the stored bytes are the transformed ones
+        s.close().unwrap();
+        let mut raw = Store::new(file.reopen().unwrap(), Config::default()).unwrap();
+        assert_ne!(value, raw.read_at_offset(0).unwrap());
+
+        let mut s = Store::new(file.into_file(), c).unwrap();
+        assert_eq!(value, s.read_at_offset(0).unwrap());
+    }
+
+    #[test]
+    fn test_store_checksum() {
+        let file = Builder::new()
+            .append(true)
+            .prefix("store-checksum-test")
+            .tempfile()
+            .unwrap();
+        let mut s = Store::new(file.reopen().unwrap(), Config::default()).unwrap();
+        test_append(&mut s);
+        s.close().unwrap();
+
+        // flip a payload byte of the second record
+        let f = file.reopen().unwrap();
+        let at = WIDTH + HEADER_WIDTH + 3;
+        let mut b = [0u8; 1];
+        read_file_exact_at(&f, &mut b, at).unwrap();
+        let mut w = OpenOptions::new().write(true).open(file.path()).unwrap();
+        w.seek(SeekFrom::Start(at)).unwrap();
+        w.write_all(&[b[0] ^ 0x01]).unwrap();
+
+        let mut s = Store::new(file.reopen().unwrap(), Config::default()).unwrap();
+        assert_eq!(DUMMY_MSG, s.read_at_offset(0).unwrap());
+        let err = s.read_at_offset(WIDTH).map_err(|e| e.kind());
+        assert_eq!(err, Err(std::io::ErrorKind::InvalidData));
+        assert_eq!(DUMMY_MSG, s.read_at_offset(WIDTH * 2).unwrap());
+    }
+
+    #[test]
+    fn test_store_legacy_frames() {
+        let file = Builder::new()
+            .append(true)
+            .prefix("store-legacy-frames-test")
+            .tempfile()
+            .unwrap();
+        // a store written before checksums holds frames with a plain length prefix
+        let (mut legacy, _) = open_file(file.path()).unwrap();
+        legacy
+            .write_all(&(DUMMY_MSG.len() as u64).to_be_bytes())
+            .unwrap();
+        legacy.write_all(DUMMY_MSG).unwrap();
+
+        let (f, _) = open_file(file.path()).unwrap();
+        let mut s = Store::new(f, Config::default()).unwrap();
+        let (_, pos) = s.append(b"checked").unwrap();
+        assert_eq!(LEN_WIDTH + DUMMY_MSG.len() as u64, pos);
+        let got: Vec<Vec<u8>> = s.records().map(Result::unwrap).collect();
+        assert_eq!(vec![DUMMY_MSG.to_vec(), b"checked".to_vec()], got);
+
+        s.close().unwrap();
+        let (mut unknown, _) = open_file(file.path()).unwrap();
+        unknown
+            .write_all(&(7u64 << VERSION_SHIFT).to_be_bytes())
+            .unwrap();
+        let mut s = Store::new(file.reopen().unwrap(), Config::default()).unwrap();
+        let err = s.read_at_offset(s.size() - LEN_WIDTH).map_err(|e| e.kind());
+        assert_eq!(err, Err(std::io::ErrorKind::InvalidData));
+    }
+
+    #[test]
+    fn test_store_append_batch() {
+        let c = Config {
+            transforms: std::sync::Arc::new(vec![Box::new(
+                crate::log::transform::tests::RunLength,
+            )]),
+            ..Default::default()
+        };
+        let msgs: [&[u8]; 4] = [b"first", b"", b"aaaaaaaabbbb", b"fourth record"];
+
+        let single = Builder::new()
+            .prefix("store-batch-test")
+            .tempfile()
+            .unwrap();
+        let mut s = Store::new(single.reopen().unwrap(), c.clone()).unwrap();
+        s.append(DUMMY_MSG).unwrap();
+        let want: Vec<(u64, u64)> = msgs.iter().map(|msg| s.append(msg).unwrap()).collect();
+        s.close().unwrap();
+
+        let batch = Builder::new()
+            .prefix("store-batch-test")
+            .tempfile()
+            .unwrap();
+        let mut s = Store::new(batch.reopen().unwrap(), c.clone()).unwrap();
+        s.append(DUMMY_MSG).unwrap();
+        assert_eq!(want, s.append_batch(&msgs).unwrap());
+        assert_eq!(Vec::<(u64, u64)>::new(), s.append_batch(&[]).unwrap());
+        for (msg, (_, pos)) in msgs.iter().zip(want.iter()) {
+            assert_eq!(*msg, &s.read_at_offset(*pos).unwrap()[..]);
+        }
+        s.close().unwrap();
+
+        let single = std::fs::read(single.path()).unwrap();
+        assert_eq!(single, std::fs::read(batch.path()).unwrap());
+    }
+
+    #[test]
+    fn test_store_iter() {
+        let file = Builder::new()
+            .append(true)
+            .prefix("store-iter-test")
+            .tempfile()
+            .unwrap();
+        let mut s = Store::new(file.reopen().unwrap(), Config::default()).unwrap();
+        test_append(&mut s);
+        let got: Vec<(u64, Vec<u8>)> = s.iter().map(Result::unwrap).collect();
+        let want: Vec<(u64, Vec<u8>)> = (0..3).map(|i| (i * WIDTH, DUMMY_MSG.to_vec())).collect();
+        assert_eq!(want, got);
+        s.close().unwrap();
+
+        // a record cut short by a crash is reported, then iteration stops
+        file.as_file().set_len(WIDTH * 3 - 2).unwrap();
+        let mut s = Store::new(file.reopen().unwrap(), Config::default()).unwrap();
+        let mut iter = s.iter();
+        assert_eq!((WIDTH, DUMMY_MSG.to_vec()), iter.nth(1).unwrap().unwrap());
+        let err = iter.next().unwrap().map_err(|e| e.kind());
+        assert_eq!(err, Err(std::io::ErrorKind::UnexpectedEof));
+        assert!(iter.next().is_none());
+        assert_eq!(2, s.records().map(Result::unwrap).count());
+    }
+
+    #[test]
+    fn test_store_read_smaller_than_buffer() {
+        for read_buffer_bytes in [0u64, 4096] {
+            let file = Builder::new()
+                .append(true)
+                .prefix("store-read-short-test")
+                .tempfile()
+                .unwrap();
+            let c = Config {
+                read_buffer_bytes,
+                ..Default::default()
+            };
+            let mut s = Store::new(file.reopen().unwrap(), c).unwrap();
+            s.append(DUMMY_MSG).unwrap();
+
+            let mut buf = vec![0xffu8; 1024];
+            assert_eq!(WIDTH as usize, s.read(&mut buf).unwrap());
+            assert_eq!(DUMMY_MSG, &buf[HEADER_WIDTH as usize..WIDTH as usize]);
+            assert!(buf[WIDTH as usize..].iter().all(|&b| b == 0xff));
+            assert_eq!(0, s.read(&mut buf).unwrap());
+        }
+    }
+
+    #[test]
+    fn test_store_sync() {
+        let file = Builder::new()
+            .append(true)
+            .prefix("store-sync-test")
+            .tempfile()
+            .unwrap();
+        let mut s = Store::new(file.reopen().unwrap(), Config::default()).unwrap();
+        test_append(&mut s);
+        assert_eq!(0, file.as_file().metadata().unwrap().len());
+
+        s.sync().unwrap();
+        assert_eq!(WIDTH * 3, file.as_file().metadata().unwrap().len());
+        test_read(&mut s);
+    }
 }
@@ -0,0 +1,101 @@
+use std::{fmt::Debug, sync::Arc};
+
+/// Rewrites record bytes on their way into and out of a store, e.g. to compress or
+/// encrypt them.
+///
+/// `on_read` must undo `on_write`. `name` identifies the transform in the pipeline a log
+/// records on disk, so it should stay the same across releases.
+pub trait Transform: Debug + Send + Sync {
+    fn name(&self) -> &str;
+    fn on_write(&self, value: &[u8]) -> Vec<u8>;
+    fn on_read(&self, value: &[u8]) -> Vec<u8>;
+}
+
+/// An ordered pipeline of transforms, applied first to last on write and last to first
+/// on read.
+pub type Transforms = Arc<Vec<Box<dyn Transform>>>;
+
+pub fn apply_write(transforms: &[Box<dyn Transform>], value: &[u8]) -> Vec<u8> {
+    let mut value = value.to_vec();
+    for transform in transforms.iter() {
+        value = transform.on_write(&value);
+    }
+    value
+}
+
+pub fn apply_read(transforms: &[Box<dyn Transform>], value: Vec<u8>) -> Vec<u8> {
+    let mut value = value;
+    for transform in transforms.iter().rev() {
+        value = transform.on_read(&value);
+    }
+    value
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    /// Run-length encodes bytes as `(count, byte)` pairs.
+    #[derive(Debug)]
+    pub(crate) struct RunLength;
+
+    impl Transform for RunLength {
+        fn name(&self) -> &str {
+            "run_length"
+        }
+
+        fn on_write(&self, value: &[u8]) -> Vec<u8> {
+            let mut out = Vec::new();
+            for &byte in value {
+                match out.len() {
+                    n if n >= 2 && out[n - 1] == byte && out[n - 2] < u8::MAX => out[n - 2] += 1,
+                    _ => out.extend_from_slice(&[1, byte]),
+                }
+            }
+            out
+        }
+
+        fn on_read(&self, value: &[u8]) -> Vec<u8> {
+            value
+                .chunks(2)
+                .flat_map(|pair| std::iter::repeat_n(pair[1], pair[0] as usize))
+                .collect()
+        }
+    }
+
+    /// XORs bytes with a repeating key.
+    #[derive(Debug)]
+    pub(crate) struct Xor(pub Vec<u8>);
+
+    impl Transform for Xor {
+        fn name(&self) -> &str {
+            "xor"
+        }
+
+        fn on_write(&self, value: &[u8]) -> Vec<u8> {
+            value
+                .iter()
+                .zip(self.0.iter().cycle())
+                .map(|(b, k)| b ^ k)
+                .collect()
+        }
+
+        fn on_read(&self, value: &[u8]) -> Vec<u8> {
+            self.on_write(value)
+        }
+    }
+
+    #[test]
+    fn test_pipeline_order() {
+        let transforms: Vec<Box<dyn Transform>> =
+            vec![Box::new(RunLength), Box::new(Xor(b"key".to_vec()))];
+        let value = b"aaaaabbbbbbbbc".to_vec();
+
+        let stored = apply_write(&transforms, &value);
+        assert_eq!(
+            Xor(b"key".to_vec()).on_write(&RunLength.on_write(&value)),
+            stored
+        );
+        assert_eq!(value, apply_read(&transforms, stored));
+    }
+}
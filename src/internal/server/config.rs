@@ -0,0 +1,33 @@
+use std::time::Duration;
+
+use serde::Serialize;
+
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct ServerConfig {
+    /// Cap on the serialized records a range consume puts in one response.
+    pub max_response_bytes: usize,
+    /// Produce requests allowed to run at once; `None` leaves them unbounded.
+    pub max_inflight_produce: Option<usize>,
+    /// How long a produce over the in-flight limit waits for a slot before a 503.
+    pub produce_queue_timeout: Duration,
+    /// How long a consume page token stays valid after the first page.
+    pub page_token_ttl: Duration,
+    /// How long a consume may wait on the log before it gets a 504; `None` waits forever.
+    pub read_timeout: Option<Duration>,
+    /// How often a gRPC consume stream waiting for appends sends a checkpoint of where
+    /// it has got to; `None` sends none.
+    pub consume_heartbeat_interval: Option<Duration>,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        Self {
+            max_response_bytes: 4 * 1024 * 1024,
+            max_inflight_produce: None,
+            produce_queue_timeout: Duration::ZERO,
+            page_token_ttl: Duration::from_secs(10 * 60),
+            read_timeout: None,
+            consume_heartbeat_interval: Some(Duration::from_secs(10)),
+        }
+    }
+}
@@ -0,0 +1,67 @@
+use std::{fmt, str::FromStr};
+
+use super::log::Record;
+
+/// A server-side consume filter.
+///
+/// The grammar is deliberately tiny: `contains:<text>` matches records whose
+/// value contains `<text>` as a byte substring.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Filter {
+    ValueContains(Vec<u8>),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseFilterError(String);
+
+impl fmt::Display for ParseFilterError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid filter: {}", self.0)
+    }
+}
+
+impl FromStr for Filter {
+    type Err = ParseFilterError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once(':') {
+            Some(("contains", text)) if !text.is_empty() => {
+                Ok(Filter::ValueContains(text.as_bytes().to_vec()))
+            }
+            _ => Err(ParseFilterError(s.to_string())),
+        }
+    }
+}
+
+impl Filter {
+    pub fn matches(&self, record: &Record) -> bool {
+        match self {
+            Filter::ValueContains(needle) => record
+                .value
+                .windows(needle.len())
+                .any(|window| window == &needle[..]),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_filter() {
+        let filter: Filter = "contains:ell".parse().unwrap();
+        assert_eq!(Filter::ValueContains(b"ell".to_vec()), filter);
+
+        let record = Record {
+            value: b"hello".to_vec(),
+            offset: 0,
+        };
+        assert!(filter.matches(&record));
+        assert!(!"contains:bye".parse::<Filter>().unwrap().matches(&record));
+
+        assert!("contains:".parse::<Filter>().is_err());
+        assert!("ell".parse::<Filter>().is_err());
+        assert!("regex:.*".parse::<Filter>().is_err());
+    }
+}
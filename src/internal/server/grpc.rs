@@ -0,0 +1,1022 @@
+use std::{
+    fs::File,
+    future::Future,
+    io::{self, ErrorKind, Read, Write},
+    net::SocketAddr,
+    path::Path,
+    time::Duration,
+};
+
+use tokio::{
+    sync::mpsc,
+    time::{self, Interval, MissedTickBehavior},
+};
+use tokio_stream::wrappers::ReceiverStream;
+use tonic::{
+    transport::{server::Router, Channel, Endpoint, Server},
+    Request, Response, Status, Streaming,
+};
+
+use crate::log::{
+    config::Config,
+    log::{Log, SegmentSnapshot},
+    segment,
+};
+
+use super::{
+    config::ServerConfig,
+    log::{
+        log_client::LogClient,
+        log_server::{self, LogServer},
+        Checkpoint, ConsumeRequest, ConsumeResponse, FetchSnapshotRequest, LogError,
+        ProduceRequest, ProduceResponse, Record, SnapshotChunk, SnapshotFile, FILE_DESCRIPTOR_SET,
+    },
+    SharedLog,
+};
+
+/// The generated `Log` service, backed by the same log the HTTP router serves.
+pub struct LogService {
+    log: SharedLog,
+    heartbeat_interval: Option<Duration>,
+}
+
+impl LogService {
+    pub fn new(log: SharedLog) -> Self {
+        Self {
+            log,
+            heartbeat_interval: ServerConfig::default().consume_heartbeat_interval,
+        }
+    }
+
+    /// Sets how often a consume stream waiting for appends sends a checkpoint; `None`
+    /// sends none.
+    pub fn with_heartbeat_interval(mut self, interval: Option<Duration>) -> Self {
+        self.heartbeat_interval = interval;
+        self
+    }
+}
+
+// responses a tailing consumer can fall behind by before reads wait on it
+const CONSUME_STREAM_BUFFER: usize = 16;
+
+// responses a streaming producer can fall behind by before appends wait on it
+const PRODUCE_STREAM_BUFFER: usize = 256;
+
+// records a streaming produce appends between flushes of the log
+const PRODUCE_STREAM_FLUSH_RECORDS: usize = 1024;
+
+// file bytes a snapshot sends per chunk, and chunks it reads ahead of the client
+const SNAPSHOT_CHUNK_BYTES: usize = 64 * 1024;
+const SNAPSHOT_BUFFER: usize = 4;
+
+#[tonic::async_trait]
+impl log_server::Log for LogService {
+    type ConsumeStreamStream = ReceiverStream<Result<ConsumeResponse, Status>>;
+    type ProduceStreamStream = ReceiverStream<Result<ProduceResponse, Status>>;
+    type FetchSnapshotStream = ReceiverStream<Result<SnapshotChunk, Status>>;
+
+    async fn produce(
+        &self,
+        req: Request<ProduceRequest>,
+    ) -> Result<Response<ProduceResponse>, Status> {
+        let record = req
+            .into_inner()
+            .record
+            .ok_or_else(|| Status::invalid_argument("missing record"))?;
+        let mut log = self.log.write().expect("poisoned write lock");
+        match log.append(record) {
+            Ok(offset) => Ok(Response::new(ProduceResponse { offset })),
+            Err(err) => Err(log_error_status(err)),
+        }
+    }
+
+    async fn consume(
+        &self,
+        req: Request<ConsumeRequest>,
+    ) -> Result<Response<ConsumeResponse>, Status> {
+        let offset = req.into_inner().offset;
+        // reads flush the store's write buffer, so they need the log exclusively
+        let mut log = self.log.write().expect("poisoned write lock");
+        match log.read_at_offset(offset) {
+            Ok(Some(record)) => Ok(Response::new(ConsumeResponse {
+                record: Some(record),
+                checkpoint: None,
+            })),
+            Ok(None) => Err(log_error_status(LogError::ErrOffsetNotFound.into())),
+            Err(err) => Err(log_error_status(err)),
+        }
+    }
+
+    /// Streams the records from the first request's offset on, waiting at the end of the
+    /// log for new appends until the client goes away.
+    ///
+    /// Every later request seeks the stream to its offset, answered with a checkpoint at
+    /// that offset before the records from there on; records already queued before it may
+    /// still arrive first. While it waits, a checkpoint with the offset it has reached goes
+    /// out every heartbeat interval, so a client knows where to resume even if nothing is
+    /// appended.
+    async fn consume_stream(
+        &self,
+        req: Request<Streaming<ConsumeRequest>>,
+    ) -> Result<Response<Self::ConsumeStreamStream>, Status> {
+        let mut requests = req.into_inner();
+        let mut offset = match requests.message().await? {
+            Some(req) => req.offset,
+            None => return Err(Status::invalid_argument("missing start offset")),
+        };
+        let (seek_tx, mut seeks) = mpsc::channel(1);
+        tokio::spawn(async move {
+            // a client that stops sending, or fails to, just can't seek any more
+            while let Ok(Some(req)) = requests.message().await {
+                if seek_tx.send(req.offset).await.is_err() {
+                    return;
+                }
+            }
+        });
+
+        let log = self.log.clone();
+        let mut heartbeat = self.heartbeat_interval.map(|period| {
+            let mut heartbeat = time::interval_at(time::Instant::now() + period, period);
+            heartbeat.set_missed_tick_behavior(MissedTickBehavior::Delay);
+            heartbeat
+        });
+        let (tx, rx) = mpsc::channel(CONSUME_STREAM_BUFFER);
+        tokio::spawn(async move {
+            let notify = log.read().expect("poisoned read lock").append_notify();
+            loop {
+                if let Ok(to) = seeks.try_recv() {
+                    offset = to;
+                    if tx.send(Ok(checkpoint(offset))).await.is_err() {
+                        return;
+                    }
+                }
+                // registered before reading, so an append right after the read still wakes it
+                let notified = notify.notified();
+                let next = read_next(&mut log.write().expect("poisoned write lock"), offset);
+                let record = match next {
+                    Ok(Next::Record(record)) => record,
+                    Ok(Next::Gap) => {
+                        offset += 1;
+                        continue;
+                    }
+                    Ok(Next::End) => {
+                        tokio::select! {
+                            _ = notified => continue,
+                            _ = tx.closed() => return,
+                            Some(to) = seeks.recv() => offset = to,
+                            _ = tick(&mut heartbeat) => {}
+                        }
+                        if tx.send(Ok(checkpoint(offset))).await.is_err() {
+                            return;
+                        }
+                        continue;
+                    }
+                    Err(err) => {
+                        let _ = tx.send(Err(log_error_status(err))).await;
+                        return;
+                    }
+                };
+                offset = record.offset + 1;
+                let resp = ConsumeResponse {
+                    record: Some(record),
+                    checkpoint: None,
+                };
+                if tx.send(Ok(resp)).await.is_err() {
+                    return;
+                }
+            }
+        });
+        Ok(Response::new(ReceiverStream::new(rx)))
+    }
+
+    /// Appends each streamed record as it arrives, answering with its offset in order.
+    ///
+    /// The log is flushed every `PRODUCE_STREAM_FLUSH_RECORDS` records and once the client
+    /// finishes sending. The first failure is sent back and ends the stream.
+    async fn produce_stream(
+        &self,
+        req: Request<Streaming<ProduceRequest>>,
+    ) -> Result<Response<Self::ProduceStreamStream>, Status> {
+        let mut requests = req.into_inner();
+        let log = self.log.clone();
+        let (tx, rx) = mpsc::channel(PRODUCE_STREAM_BUFFER);
+        tokio::spawn(async move {
+            let mut unflushed = 0;
+            loop {
+                let record = match requests.message().await {
+                    Ok(Some(req)) => req.record,
+                    Ok(None) => break,
+                    Err(status) => {
+                        let _ = tx.send(Err(status)).await;
+                        break;
+                    }
+                };
+                let resp = match record {
+                    Some(record) => append(&log, record, &mut unflushed)
+                        .map(|offset| ProduceResponse { offset })
+                        .map_err(log_error_status),
+                    None => Err(Status::invalid_argument("missing record")),
+                };
+                let failed = resp.is_err();
+                if tx.send(resp).await.is_err() || failed {
+                    break;
+                }
+            }
+            if unflushed > 0 {
+                let _ = log.write().expect("poisoned write lock").flush();
+            }
+        });
+        Ok(Response::new(ReceiverStream::new(rx)))
+    }
+
+    /// Streams the store and then the index of every sealed segment, in offset order.
+    ///
+    /// The segments are fixed when the call starts: records appended after it, and
+    /// segments sealed since, are left out.
+    async fn fetch_snapshot(
+        &self,
+        _req: Request<FetchSnapshotRequest>,
+    ) -> Result<Response<Self::FetchSnapshotStream>, Status> {
+        let segments = self
+            .log
+            .write()
+            .expect("poisoned write lock")
+            .snapshot_sealed()
+            .map_err(log_error_status)?;
+        let (tx, rx) = mpsc::channel(SNAPSHOT_BUFFER);
+        tokio::task::spawn_blocking(move || {
+            for segment in segments {
+                match send_segment(&tx, segment) {
+                    Ok(true) => {}
+                    Ok(false) => return,
+                    Err(err) => {
+                        let _ = tx.blocking_send(Err(log_error_status(err)));
+                        return;
+                    }
+                }
+            }
+        });
+        Ok(Response::new(ReceiverStream::new(rx)))
+    }
+}
+
+// sends a segment's files in chunks, returning false once the client has gone
+fn send_segment(
+    tx: &mpsc::Sender<Result<SnapshotChunk, Status>>,
+    segment: SegmentSnapshot,
+) -> io::Result<bool> {
+    let files = [
+        (SnapshotFile::Store, segment.store),
+        (SnapshotFile::Index, segment.index),
+    ];
+    for (file, (mut f, len)) in files {
+        let mut position = 0;
+        loop {
+            let mut data = vec![0; (len - position).min(SNAPSHOT_CHUNK_BYTES as u64) as usize];
+            f.read_exact(&mut data)?;
+            let n = data.len() as u64;
+            let chunk = SnapshotChunk {
+                base_offset: segment.base_offset,
+                file: file.into(),
+                position,
+                data,
+            };
+            if tx.blocking_send(Ok(chunk)).is_err() {
+                return Ok(false);
+            }
+            position += n;
+            if position >= len {
+                break;
+            }
+        }
+    }
+    Ok(true)
+}
+
+/// Copies the sealed segments of the server at `endpoint` into `dir`, then opens the
+/// log there, e.g. to start a replica without replaying every record.
+///
+/// `dir` must be empty or not exist yet, and `config` has to lay out its files the way
+/// the server's does. The copied segments keep none of their write times, so retention
+/// counts their age from the copy.
+pub async fn bootstrap<P: AsRef<Path>>(
+    endpoint: Endpoint,
+    dir: P,
+    config: Config,
+) -> io::Result<Log> {
+    let dir = dir.as_ref();
+    std::fs::create_dir_all(dir)?;
+    if std::fs::read_dir(dir)?.next().is_some() {
+        return Err(io::Error::new(
+            ErrorKind::AlreadyExists,
+            format!("can not bootstrap into {}, it isn't empty", dir.display()),
+        ));
+    }
+    let channel = endpoint.connect().await.map_err(io::Error::other)?;
+    let mut chunks = LogClient::new(channel)
+        .fetch_snapshot(FetchSnapshotRequest {})
+        .await
+        .map_err(io::Error::other)?
+        .into_inner();
+    let mut current: Option<(u64, SnapshotFile, File, u64)> = None;
+    while let Some(chunk) = chunks.message().await.map_err(io::Error::other)? {
+        let file = chunk.file();
+        let continues = matches!(
+            current,
+            Some((base_offset, kind, _, _)) if base_offset == chunk.base_offset && kind == file
+        );
+        if !continues {
+            if let Some((_, _, f, _)) = current.take() {
+                f.sync_all()?;
+            }
+            let ext = match file {
+                SnapshotFile::Store => "store",
+                SnapshotFile::Index => "index",
+            };
+            let path = dir.join(format!("{}.{}", chunk.base_offset, ext));
+            let f = File::options().write(true).create_new(true).open(path)?;
+            current = Some((chunk.base_offset, file, f, 0));
+        }
+        let (_, _, f, written) = current.as_mut().expect("a file is open");
+        if chunk.position != *written {
+            return Err(io::Error::new(
+                ErrorKind::InvalidData,
+                format!(
+                    "snapshot chunk of segment {} at {}, expected {}",
+                    chunk.base_offset, chunk.position, written
+                ),
+            ));
+        }
+        f.write_all(&chunk.data)?;
+        *written += chunk.data.len() as u64;
+    }
+    if let Some((_, _, f, _)) = current {
+        f.sync_all()?;
+    }
+    segment::sync_dir(dir)?;
+    Log::new(dir, config)
+}
+
+// appends one streamed record, flushing the log once enough have gone unflushed
+fn append(log: &SharedLog, record: Record, unflushed: &mut usize) -> io::Result<u64> {
+    let mut log = log.write().expect("poisoned write lock");
+    let offset = log.append(record)?;
+    *unflushed += 1;
+    if *unflushed >= PRODUCE_STREAM_FLUSH_RECORDS {
+        log.flush()?;
+        *unflushed = 0;
+    }
+    Ok(offset)
+}
+
+// a consume stream response saying the stream carries on from `next_offset`
+fn checkpoint(next_offset: u64) -> ConsumeResponse {
+    ConsumeResponse {
+        record: None,
+        checkpoint: Some(Checkpoint { next_offset }),
+    }
+}
+
+// waits for the next heartbeat, or forever without one
+async fn tick(heartbeat: &mut Option<Interval>) {
+    match heartbeat {
+        Some(heartbeat) => {
+            heartbeat.tick().await;
+        }
+        None => std::future::pending().await,
+    }
+}
+
+// what a tailing read found at an offset
+enum Next {
+    Record(Record),
+    // nothing is stored at the offset, but later offsets may hold records
+    Gap,
+    // the offset is past the end of the log
+    End,
+}
+
+fn read_next(log: &mut Log, offset: u64) -> io::Result<Next> {
+    match log.read_at_offset(offset) {
+        Ok(Some(record)) => Ok(Next::Record(record)),
+        Ok(None) => Err(LogError::Closed.into()),
+        Err(_) if offset >= log.next_offset() => Ok(Next::End),
+        Err(err) if matches!(err.kind(), ErrorKind::NotFound | ErrorKind::UnexpectedEof) => {
+            Ok(Next::Gap)
+        }
+        Err(err) => Err(err),
+    }
+}
+
+/// Serves the log over gRPC on `addr`, with server reflection, until the server fails.
+pub async fn serve(addr: SocketAddr, log: SharedLog) -> Result<(), tonic::transport::Error> {
+    router(log, &ServerConfig::default()).serve(addr).await
+}
+
+/// Like [`serve`] with `config`, until `shutdown` completes, then waits for the calls in
+/// flight.
+pub async fn serve_with_shutdown<F>(
+    addr: SocketAddr,
+    log: SharedLog,
+    config: &ServerConfig,
+    shutdown: F,
+) -> Result<(), tonic::transport::Error>
+where
+    F: Future<Output = ()>,
+{
+    router(log, config)
+        .serve_with_shutdown(addr, shutdown)
+        .await
+}
+
+// the `Log` service next to a reflection service describing it, for tools like grpcurl
+fn router(log: SharedLog, config: &ServerConfig) -> Router {
+    let reflection = tonic_reflection::server::Builder::configure()
+        .register_encoded_file_descriptor_set(FILE_DESCRIPTOR_SET)
+        .build()
+        .expect("the log descriptor set is valid");
+    let service = LogService::new(log).with_heartbeat_interval(config.consume_heartbeat_interval);
+    Server::builder()
+        .add_service(LogServer::new(service))
+        .add_service(reflection)
+}
+
+/// Tails a log over `ConsumeStream`, opening a new stream where the last one left off
+/// whenever it breaks, e.g. across a server restart.
+///
+/// Records and checkpoints both move the resume point, so a reconnect neither sends a
+/// delivered record again nor rereads the gaps a checkpoint covered.
+pub struct ResumingConsumer {
+    endpoint: Endpoint,
+    next_offset: u64,
+    stream: Option<Streaming<ConsumeResponse>>,
+}
+
+impl ResumingConsumer {
+    /// Starts consuming at `offset` from the server at `endpoint`, connecting on the first
+    /// call to `next`.
+    pub fn new(endpoint: Endpoint, offset: u64) -> Self {
+        Self {
+            endpoint,
+            next_offset: offset,
+            stream: None,
+        }
+    }
+
+    /// The offset a new stream would start at.
+    pub fn next_offset(&self) -> u64 {
+        self.next_offset
+    }
+
+    /// Waits for the next record, connecting first if there is no stream.
+    ///
+    /// A stream that fails is dropped and its error returned; calling `next` again
+    /// reconnects from `next_offset`. A stream the server ends is reopened straight away.
+    pub async fn next(&mut self) -> Result<Record, Status> {
+        loop {
+            let stream = match self.stream {
+                Some(ref mut stream) => stream,
+                None => {
+                    let stream = self.connect().await?;
+                    self.stream.insert(stream)
+                }
+            };
+            match stream.message().await {
+                Ok(Some(ConsumeResponse {
+                    record: Some(record),
+                    ..
+                })) => {
+                    self.next_offset = record.offset + 1;
+                    return Ok(record);
+                }
+                Ok(Some(ConsumeResponse {
+                    checkpoint: Some(checkpoint),
+                    ..
+                })) => self.next_offset = self.next_offset.max(checkpoint.next_offset),
+                Ok(Some(_)) => {}
+                Ok(None) => self.stream = None,
+                Err(status) => {
+                    self.stream = None;
+                    return Err(status);
+                }
+            }
+        }
+    }
+
+    async fn connect(&self) -> Result<Streaming<ConsumeResponse>, Status> {
+        let channel: Channel = self
+            .endpoint
+            .connect()
+            .await
+            .map_err(|err| Status::unavailable(err.to_string()))?;
+        let req = ConsumeRequest {
+            offset: self.next_offset,
+        };
+        // no seeks follow the start offset
+        let requests = tokio_stream::once(req);
+        let resp = LogClient::new(channel).consume_stream(requests).await?;
+        Ok(resp.into_inner())
+    }
+}
+
+/// Maps a log error to a status the same way the HTTP router picks a status code.
+fn log_error_status(err: io::Error) -> Status {
+    let msg = err.to_string();
+    match err.get_ref().and_then(|e| e.downcast_ref::<LogError>()) {
+        Some(LogError::ErrOffsetNotFound) => Status::not_found(msg),
+        Some(LogError::Rejected { .. }) => Status::invalid_argument(msg),
+        Some(LogError::Paused) => Status::unavailable(msg),
+        Some(LogError::Closed | LogError::SegmentClosed { .. }) => Status::failed_precondition(msg),
+        Some(LogError::Other) => Status::internal(msg),
+        None => match err.kind() {
+            ErrorKind::NotFound | ErrorKind::UnexpectedEof => Status::not_found(msg),
+            _ => Status::internal(msg),
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::{Arc, RwLock},
+        time::Duration,
+    };
+
+    use tonic::Code;
+
+    use tokio_stream::{wrappers::TcpListenerStream, StreamExt};
+
+    use crate::log::config::Config;
+    use crate::server::log::{log_client::LogClient, log_server::Log as _};
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_produce_consume() {
+        let dir = tempfile::tempdir().unwrap();
+        let log = Log::new(dir.path(), Config::default()).unwrap();
+        let service = LogService::new(Arc::new(RwLock::new(log)));
+
+        for i in 0..3 {
+            let req = ProduceRequest {
+                record: Some(Record {
+                    value: format!("record {}", i).into_bytes(),
+                    offset: 0,
+                }),
+            };
+            let resp = service.produce(Request::new(req)).await.unwrap();
+            assert_eq!(i, resp.into_inner().offset);
+        }
+
+        let resp = service
+            .consume(Request::new(ConsumeRequest { offset: 1 }))
+            .await
+            .unwrap();
+        let record = resp.into_inner().record.unwrap();
+        assert_eq!(1, record.offset);
+        assert_eq!(b"record 1".to_vec(), record.value);
+
+        let status = service
+            .consume(Request::new(ConsumeRequest { offset: 3 }))
+            .await
+            .unwrap_err();
+        assert_eq!(Code::NotFound, status.code());
+
+        let req = ProduceRequest { record: None };
+        let status = service.produce(Request::new(req)).await.unwrap_err();
+        assert_eq!(Code::InvalidArgument, status.code());
+
+        service.log.write().unwrap().pause();
+        let req = ProduceRequest {
+            record: Some(Record::default()),
+        };
+        let status = service.produce(Request::new(req)).await.unwrap_err();
+        assert_eq!(Code::Unavailable, status.code());
+    }
+
+    #[test]
+    fn test_log_error_status() {
+        let err = io::Error::from(ErrorKind::PermissionDenied);
+        assert_eq!(Code::Internal, log_error_status(err).code());
+        let err = io::Error::from(ErrorKind::UnexpectedEof);
+        assert_eq!(Code::NotFound, log_error_status(err).code());
+        let err = io::Error::from(LogError::Closed);
+        assert_eq!(Code::FailedPrecondition, log_error_status(err).code());
+        let err = io::Error::from(LogError::SegmentClosed { base_offset: 0 });
+        assert_eq!(Code::FailedPrecondition, log_error_status(err).code());
+    }
+
+    async fn next_record(stream: &mut Streaming<ConsumeResponse>) -> Record {
+        let resp = stream.message().await.unwrap().unwrap();
+        resp.record.unwrap()
+    }
+
+    async fn next_message(stream: &mut Streaming<ConsumeResponse>) -> ConsumeResponse {
+        tokio::time::timeout(Duration::from_secs(5), stream.message())
+            .await
+            .unwrap()
+            .unwrap()
+            .unwrap()
+    }
+
+    // serves `log` on a free port, returning a client for it and the server's task
+    async fn serve_log(
+        log: SharedLog,
+        config: ServerConfig,
+    ) -> (
+        LogClient<Channel>,
+        tokio::task::JoinHandle<Result<(), tonic::transport::Error>>,
+    ) {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = tokio::spawn(
+            router(log, &config).serve_with_incoming(TcpListenerStream::new(listener)),
+        );
+        let client = LogClient::connect(format!("http://{}", addr))
+            .await
+            .unwrap();
+        (client, server)
+    }
+
+    #[tokio::test]
+    async fn test_consume_stream() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = Config {
+            max_store_bytes: 32,
+            ..Default::default()
+        };
+        let log = Arc::new(RwLock::new(Log::new(dir.path(), config).unwrap()));
+        let (mut client, server) = serve_log(log.clone(), ServerConfig::default()).await;
+        let append = |value: &str| {
+            let record = Record {
+                value: value.as_bytes().to_vec(),
+                offset: 0,
+            };
+            log.write().unwrap().append(record).unwrap()
+        };
+        for value in ["first", "second", "third"] {
+            append(value);
+        }
+
+        let req = tokio_stream::once(ConsumeRequest { offset: 1 });
+        let mut stream = client.consume_stream(req).await.unwrap().into_inner();
+        assert_eq!(b"second".to_vec(), next_record(&mut stream).await.value);
+        assert_eq!(b"third".to_vec(), next_record(&mut stream).await.value);
+
+        // the stream waits at the end of the log, across segment rolls
+        let waiting = tokio::spawn(async move {
+            let mut values = Vec::new();
+            for _ in 0..3 {
+                values.push(next_record(&mut stream).await.value);
+            }
+            (stream, values)
+        });
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        assert!(!waiting.is_finished());
+        for value in ["fourth", "fifth", "sixth"] {
+            append(value);
+        }
+        let (stream, values) = tokio::time::timeout(Duration::from_secs(5), waiting)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            vec![b"fourth".to_vec(), b"fifth".to_vec(), b"sixth".to_vec()],
+            values
+        );
+
+        // dropping the stream ends the task reading for it, leaving the server's service
+        drop(stream);
+        let started = std::time::Instant::now();
+        while Arc::strong_count(&log) > 2 {
+            assert!(started.elapsed() < Duration::from_secs(5));
+            tokio::time::sleep(Duration::from_millis(5)).await;
+        }
+
+        // a stream needs a start offset
+        let req = tokio_stream::empty::<ConsumeRequest>();
+        let status = client.consume_stream(req).await.unwrap_err();
+        assert_eq!(Code::InvalidArgument, status.code());
+        server.abort();
+    }
+
+    #[tokio::test]
+    async fn test_consume_stream_seek() {
+        let dir = tempfile::tempdir().unwrap();
+        let log = Arc::new(RwLock::new(
+            Log::new(dir.path(), Config::default()).unwrap(),
+        ));
+        for i in 0..10 {
+            let record = Record {
+                value: format!("record {}", i).into_bytes(),
+                offset: 0,
+            };
+            log.write().unwrap().append(record).unwrap();
+        }
+        let (mut client, server) = serve_log(log, ServerConfig::default()).await;
+
+        let (seek, seeks) = mpsc::channel(1);
+        seek.send(ConsumeRequest { offset: 0 }).await.unwrap();
+        let mut stream = client
+            .consume_stream(ReceiverStream::new(seeks))
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(0, next_record(&mut stream).await.offset);
+
+        // back and forth over the connection already open; the checkpoint marks where
+        // records from the new position start
+        for to in [7, 2] {
+            seek.send(ConsumeRequest { offset: to }).await.unwrap();
+            loop {
+                let resp = next_message(&mut stream).await;
+                if resp.checkpoint == Some(Checkpoint { next_offset: to }) {
+                    break;
+                }
+            }
+            assert_eq!(to, next_record(&mut stream).await.offset);
+            assert_eq!(to + 1, next_record(&mut stream).await.offset);
+        }
+
+        // a seek also lands while the stream waits at the end of the log
+        while next_record(&mut stream).await.offset < 9 {}
+        seek.send(ConsumeRequest { offset: 5 }).await.unwrap();
+        let resp = next_message(&mut stream).await;
+        assert_eq!(Some(Checkpoint { next_offset: 5 }), resp.checkpoint);
+        assert_eq!(5, next_record(&mut stream).await.offset);
+        server.abort();
+    }
+
+    #[tokio::test]
+    async fn test_consume_stream_heartbeat() {
+        let dir = tempfile::tempdir().unwrap();
+        let log = Arc::new(RwLock::new(
+            Log::new(dir.path(), Config::default()).unwrap(),
+        ));
+        log.write().unwrap().append(Record::default()).unwrap();
+
+        // waiting at the end of the log, the stream says where it has got to
+        let config = ServerConfig {
+            consume_heartbeat_interval: Some(Duration::from_millis(20)),
+            ..Default::default()
+        };
+        let (mut client, server) = serve_log(log.clone(), config).await;
+        let req = tokio_stream::once(ConsumeRequest { offset: 0 });
+        let mut stream = client.consume_stream(req).await.unwrap().into_inner();
+        assert_eq!(0, next_record(&mut stream).await.offset);
+        for _ in 0..2 {
+            let resp = next_message(&mut stream).await;
+            assert_eq!(None, resp.record);
+            assert_eq!(Some(Checkpoint { next_offset: 1 }), resp.checkpoint);
+        }
+        server.abort();
+
+        // and says nothing with heartbeats off
+        let config = ServerConfig {
+            consume_heartbeat_interval: None,
+            ..Default::default()
+        };
+        let (mut client, server) = serve_log(log, config).await;
+        let req = tokio_stream::once(ConsumeRequest { offset: 1 });
+        let mut stream = client.consume_stream(req).await.unwrap().into_inner();
+        let next = tokio::time::timeout(Duration::from_millis(100), stream.message()).await;
+        assert!(next.is_err());
+        server.abort();
+    }
+
+    #[tokio::test]
+    async fn test_resuming_consumer() {
+        let dir = tempfile::tempdir().unwrap();
+        let log = Arc::new(RwLock::new(
+            Log::new(dir.path(), Config::default()).unwrap(),
+        ));
+        let append = |n: u64| {
+            let mut log = log.write().unwrap();
+            for _ in 0..n {
+                log.append(Record::default()).unwrap();
+            }
+        };
+        append(3);
+        // each server runs on a runtime of its own, so stopping it drops its connections
+        // the way a restart would
+        let start = |listener: std::net::TcpListener| {
+            let config = ServerConfig {
+                consume_heartbeat_interval: Some(Duration::from_millis(20)),
+                ..Default::default()
+            };
+            let router = router(log.clone(), &config);
+            let (stop, stopped) = tokio::sync::oneshot::channel::<()>();
+            let thread = std::thread::spawn(move || {
+                let runtime = tokio::runtime::Runtime::new().unwrap();
+                runtime.block_on(async move {
+                    listener.set_nonblocking(true).unwrap();
+                    let listener = tokio::net::TcpListener::from_std(listener).unwrap();
+                    tokio::select! {
+                        _ = router.serve_with_incoming(TcpListenerStream::new(listener)) => {}
+                        _ = stopped => {}
+                    }
+                });
+            });
+            move || {
+                stop.send(()).unwrap();
+                thread.join().unwrap();
+            }
+        };
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let stop = start(listener);
+
+        let endpoint = Endpoint::from_shared(format!("http://{}", addr)).unwrap();
+        let mut consumer = ResumingConsumer::new(endpoint, 0);
+        for offset in 0..3 {
+            assert_eq!(offset, consumer.next().await.unwrap().offset);
+        }
+        assert_eq!(3, consumer.next_offset());
+
+        // the server goes away mid-stream and comes back on the same address
+        stop();
+        let err = tokio::time::timeout(Duration::from_secs(5), consumer.next())
+            .await
+            .unwrap()
+            .unwrap_err();
+        assert_ne!(Code::Ok, err.code());
+        append(2);
+        let stop = start(std::net::TcpListener::bind(addr).unwrap());
+
+        // the reconnect picks up after the records already delivered
+        let mut offsets = Vec::new();
+        while offsets.len() < 2 {
+            match consumer.next().await {
+                Ok(record) => offsets.push(record.offset),
+                Err(_) => tokio::time::sleep(Duration::from_millis(10)).await,
+            }
+        }
+        assert_eq!(vec![3, 4], offsets);
+        stop();
+    }
+
+    #[tokio::test]
+    async fn test_bootstrap() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = Config {
+            max_store_bytes: 64,
+            create_dir: true,
+            ..Default::default()
+        };
+        let log = Arc::new(RwLock::new(
+            Log::new(dir.path().join("source"), config.clone()).unwrap(),
+        ));
+        for i in 0..50 {
+            let record = Record {
+                value: format!("record {}", i).into_bytes(),
+                offset: 0,
+            };
+            log.write().unwrap().append(record).unwrap();
+        }
+        let sealed = log.write().unwrap().snapshot_sealed().unwrap();
+        assert!(sealed.len() > 3);
+        let end = sealed.last().unwrap().next_offset;
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = tokio::spawn(
+            router(log.clone(), &ServerConfig::default())
+                .serve_with_incoming(TcpListenerStream::new(listener)),
+        );
+        let endpoint = Endpoint::from_shared(format!("http://{}", addr)).unwrap();
+
+        // the empty node ends up with every record the sealed segments hold
+        let replica = dir.path().join("replica");
+        let mut copy = bootstrap(endpoint.clone(), &replica, config.clone())
+            .await
+            .unwrap();
+        assert_eq!(end, copy.next_offset());
+        for offset in 0..end {
+            let want = log.write().unwrap().read_at_offset(offset).unwrap();
+            assert_eq!(want, copy.read_at_offset(offset).unwrap());
+        }
+        // and appends carry on from there
+        assert_eq!(end, copy.append(Record::default()).unwrap());
+
+        // a node with a log already is left alone
+        let res = bootstrap(endpoint, &replica, config).await;
+        assert_eq!(
+            Some(ErrorKind::AlreadyExists),
+            res.err().map(|err| err.kind())
+        );
+        server.abort();
+    }
+
+    #[tokio::test]
+    async fn test_produce_stream() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = Config {
+            max_store_bytes: 64 * 1024,
+            max_index_bytes: 64 * 1024,
+            ..Default::default()
+        };
+        let log = Arc::new(RwLock::new(Log::new(dir.path(), config).unwrap()));
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = Server::builder()
+            .add_service(LogServer::new(LogService::new(log.clone())))
+            .serve_with_incoming(TcpListenerStream::new(listener));
+        let server = tokio::spawn(server);
+
+        let mut client = LogClient::connect(format!("http://{}", addr))
+            .await
+            .unwrap();
+        let requests = tokio_stream::iter(0..10_000).map(|i| ProduceRequest {
+            record: Some(Record {
+                value: format!("record {}", i).into_bytes(),
+                offset: 0,
+            }),
+        });
+        let mut responses = client.produce_stream(requests).await.unwrap().into_inner();
+        let mut offsets = Vec::new();
+        while let Some(resp) = responses.message().await.unwrap() {
+            offsets.push(resp.offset);
+        }
+        assert_eq!((0..10_000).collect::<Vec<u64>>(), offsets);
+
+        let record = log.write().unwrap().read_at_offset(9_999).unwrap().unwrap();
+        assert_eq!(b"record 9999".to_vec(), record.value);
+
+        // a request without a record is answered with an error, ending the stream
+        let requests = tokio_stream::iter([ProduceRequest { record: None }]);
+        let mut responses = client.produce_stream(requests).await.unwrap().into_inner();
+        let status = responses.message().await.unwrap_err();
+        assert_eq!(Code::InvalidArgument, status.code());
+        server.abort();
+    }
+
+    #[tokio::test]
+    async fn test_reflection() {
+        use prost::Message;
+        use tonic_reflection::pb::{
+            server_reflection_client::ServerReflectionClient,
+            server_reflection_request::MessageRequest, server_reflection_response::MessageResponse,
+            ServerReflectionRequest,
+        };
+
+        let dir = tempfile::tempdir().unwrap();
+        let log = Arc::new(RwLock::new(
+            Log::new(dir.path(), Config::default()).unwrap(),
+        ));
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = tokio::spawn(
+            router(log, &ServerConfig::default())
+                .serve_with_incoming(TcpListenerStream::new(listener)),
+        );
+
+        let channel = tonic::transport::Endpoint::from_shared(format!("http://{}", addr))
+            .unwrap()
+            .connect()
+            .await
+            .unwrap();
+        let mut client = ServerReflectionClient::new(channel);
+        let requests = [
+            MessageRequest::ListServices(String::new()),
+            MessageRequest::FileContainingSymbol("log.Log".to_string()),
+        ]
+        .map(|req| ServerReflectionRequest {
+            host: String::new(),
+            message_request: Some(req),
+        });
+        let mut responses = client
+            .server_reflection_info(tokio_stream::iter(requests))
+            .await
+            .unwrap()
+            .into_inner();
+
+        let services = match responses.message().await.unwrap().unwrap().message_response {
+            Some(MessageResponse::ListServicesResponse(list)) => list.service,
+            resp => panic!("unexpected response {:?}", resp),
+        };
+        assert!(services.iter().any(|service| service.name == "log.Log"));
+
+        let files = match responses.message().await.unwrap().unwrap().message_response {
+            Some(MessageResponse::FileDescriptorResponse(files)) => files.file_descriptor_proto,
+            resp => panic!("unexpected response {:?}", resp),
+        };
+        let file = prost_types::FileDescriptorProto::decode(&files[0][..]).unwrap();
+        let service = file
+            .service
+            .iter()
+            .find(|service| service.name() == "Log")
+            .unwrap();
+        let methods: Vec<&str> = service.method.iter().map(|method| method.name()).collect();
+        assert_eq!(
+            vec![
+                "Produce",
+                "Consume",
+                "ConsumeStream",
+                "ProduceStream",
+                "FetchSnapshot"
+            ],
+            methods
+        );
+        server.abort();
+    }
+}
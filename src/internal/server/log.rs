@@ -1,49 +1,57 @@
 use core::fmt;
+use std::sync::Arc;
 
 use serde_with::{base64::Base64, serde_as};
 
 tonic::include_proto!("log");
 
+/// Encoded descriptors of the `log` proto, for gRPC reflection.
+pub const FILE_DESCRIPTOR_SET: &[u8] = tonic::include_file_descriptor_set!("log_descriptor");
+
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum LogError {
     ErrOffsetNotFound,
+    Rejected {
+        reason: String,
+    },
+    Paused,
+    Closed,
+    /// A read landed in a closed segment, which `Config::reopen_closed_segments` didn't
+    /// allow reopening.
+    SegmentClosed {
+        base_offset: u64,
+    },
     Other,
 }
 
 impl fmt::Display for LogError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        let msg = match &self {
-            Self::ErrOffsetNotFound => "offset not found",
-            Self::Other => "weird error occurs",
-        };
-
-        write!(f, "{}", msg)
-    }
-}
-
-#[derive(Debug, Clone, Default)]
-pub struct Log {
-    records: Vec<Record>,
-}
-
-impl Log {
-    pub fn new() -> Self {
-        Self {
-            records: Vec::new(),
+        match &self {
+            Self::ErrOffsetNotFound => write!(f, "offset not found"),
+            Self::Rejected { reason } => write!(f, "record rejected: {}", reason),
+            Self::Paused => write!(f, "appends are paused"),
+            Self::Closed => write!(f, "log is closed"),
+            Self::SegmentClosed { base_offset } => write!(f, "segment {} is closed", base_offset),
+            Self::Other => write!(f, "weird error occurs"),
         }
     }
+}
 
-    pub fn append(&mut self, mut record: Record) -> Result<u64, LogError> {
-        let offset = self.records.len() as u64;
-        record.offset = offset;
-        self.records.push(record);
-        return Ok(offset);
-    }
-
-    pub fn read(&self, offset: u64) -> Result<Record, LogError> {
-        if offset >= self.records.len() as u64 {
-            return Err(LogError::ErrOffsetNotFound);
-        }
-
-        return Ok(self.records[offset as usize].clone());
+impl std::error::Error for LogError {}
+
+impl From<LogError> for std::io::Error {
+    fn from(err: LogError) -> Self {
+        use std::io::ErrorKind;
+        let kind = match err {
+            LogError::ErrOffsetNotFound => ErrorKind::NotFound,
+            LogError::Rejected { .. } => ErrorKind::InvalidInput,
+            LogError::Paused => ErrorKind::WouldBlock,
+            LogError::Closed | LogError::SegmentClosed { .. } => ErrorKind::Other,
+            LogError::Other => ErrorKind::Other,
+        };
+        Self::new(kind, err)
     }
 }
+
+/// Checks a record before it is appended, returning why it was rejected otherwise.
+pub type Validator = Arc<dyn Fn(&Record) -> Result<(), String> + Send + Sync>;
@@ -1,4 +1,14 @@
+pub mod config;
+pub mod filter;
+pub mod grpc;
 pub mod log;
+pub mod page;
 pub mod router;
+pub mod run;
+pub mod tls;
 
 pub use router::*;
+pub use run::{run, RunConfig};
+pub use tls::{
+    serve_tls, serve_tls_with_config, serve_tls_with_shutdown, ClientIdentity, ServerTlsConfig,
+};
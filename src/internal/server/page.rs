@@ -0,0 +1,135 @@
+use std::{
+    fmt,
+    str::FromStr,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use byteorder::{BigEndian, ByteOrder};
+
+const TOKEN_LEN: usize = 24;
+
+/// Where a paginated consume left off.
+///
+/// `high_watermark` is the log's next offset when the first page was served. Pages never
+/// go past it, so records appended mid-pagination don't shift what the client iterates.
+/// The token is the URL-safe base64 of the three fields as big-endian `u64`s.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PageToken {
+    pub next_offset: u64,
+    pub high_watermark: u64,
+    /// Seconds since the Unix epoch when the first page was served.
+    pub issued_at: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PageTokenError {
+    Malformed,
+    Expired,
+}
+
+impl fmt::Display for PageTokenError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Malformed => write!(f, "malformed page token"),
+            Self::Expired => write!(f, "page token expired"),
+        }
+    }
+}
+
+impl PageToken {
+    pub fn new(next_offset: u64, high_watermark: u64) -> Self {
+        Self {
+            next_offset,
+            high_watermark,
+            issued_at: now_secs(),
+        }
+    }
+
+    /// The token for the page after this one, pinned to the same watermark and issue time.
+    pub fn next(&self, next_offset: u64) -> Self {
+        Self {
+            next_offset,
+            ..*self
+        }
+    }
+
+    pub fn check_expiry(&self, ttl: Duration) -> Result<(), PageTokenError> {
+        if now_secs() > self.issued_at.saturating_add(ttl.as_secs()) {
+            return Err(PageTokenError::Expired);
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for PageToken {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut buf = [0u8; TOKEN_LEN];
+        BigEndian::write_u64(&mut buf[0..8], self.next_offset);
+        BigEndian::write_u64(&mut buf[8..16], self.high_watermark);
+        BigEndian::write_u64(&mut buf[16..24], self.issued_at);
+        write!(f, "{}", URL_SAFE_NO_PAD.encode(buf))
+    }
+}
+
+impl FromStr for PageToken {
+    type Err = PageTokenError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let buf = URL_SAFE_NO_PAD
+            .decode(s)
+            .map_err(|_| PageTokenError::Malformed)?;
+        if buf.len() != TOKEN_LEN {
+            return Err(PageTokenError::Malformed);
+        }
+        let token = PageToken {
+            next_offset: BigEndian::read_u64(&buf[0..8]),
+            high_watermark: BigEndian::read_u64(&buf[8..16]),
+            issued_at: BigEndian::read_u64(&buf[16..24]),
+        };
+        if token.next_offset > token.high_watermark {
+            return Err(PageTokenError::Malformed);
+        }
+        Ok(token)
+    }
+}
+
+fn now_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_page_token() {
+        let token = PageToken::new(5, 12);
+        let parsed: PageToken = token.to_string().parse().unwrap();
+        assert_eq!(token, parsed);
+        assert!(parsed.check_expiry(Duration::from_secs(60)).is_ok());
+
+        let next = parsed.next(9);
+        assert_eq!(12, next.high_watermark);
+        assert_eq!(token.issued_at, next.issued_at);
+
+        let stale = PageToken {
+            issued_at: token.issued_at - 120,
+            ..token
+        };
+        assert_eq!(
+            Err(PageTokenError::Expired),
+            stale.check_expiry(Duration::from_secs(60))
+        );
+
+        assert!("not a token!".parse::<PageToken>().is_err());
+        assert!("AAAA".parse::<PageToken>().is_err());
+        let backwards = PageToken::new(13, 12).to_string();
+        assert_eq!(
+            Err(PageTokenError::Malformed),
+            backwards.parse::<PageToken>()
+        );
+    }
+}
@@ -1,15 +1,36 @@
-use std::sync::{Arc, RwLock};
+use std::{
+    future::Future,
+    io::{self, ErrorKind},
+    net::SocketAddr,
+    sync::{Arc, RwLock},
+    time::Duration,
+};
 
 use axum::{
-    extract::State,
-    http::StatusCode,
+    body::StreamBody,
+    extract::{Path, Query, State},
+    http::{header, StatusCode},
     response::{IntoResponse, Response},
     routing::{get, post},
     Json, Router,
 };
 use serde::{Deserialize, Serialize};
+use tokio::sync::{mpsc, Semaphore};
+use tokio_stream::wrappers::ReceiverStream;
+
+use crate::log::{
+    config::Config,
+    log::{CompactionStats, Log},
+    segment::IndexEntry,
+};
 
-use super::log::{Log, LogError, Record};
+use super::{
+    config::ServerConfig,
+    filter::Filter,
+    log::{LogError, Record},
+    page::PageToken,
+    tls::{self, ServerTlsConfig},
+};
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct ProduceRequest {
@@ -31,28 +52,216 @@ pub struct ConsumeResponse {
     record: Record,
 }
 
-type RouterState = Arc<RwLock<Log>>;
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct ContextQuery {
+    #[serde(default)]
+    before: u64,
+    #[serde(default)]
+    after: u64,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct ContextResponse {
+    start: u64,
+    end: u64,
+    records: Vec<Record>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct ConsumeRangeQuery {
+    from: Option<u64>,
+    page_token: Option<String>,
+    #[serde(rename = "match")]
+    filter: Option<String>,
+    limit: Option<usize>,
+    max_bytes: Option<usize>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct ConsumeRangeResponse {
+    records: Vec<Record>,
+    next_offset: u64,
+    /// Continues from `next_offset` up to the first page's watermark; `None` once there.
+    next_page_token: Option<String>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct ScanQuery {
+    from: Option<u64>,
+    to: Option<u64>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct TailQuery {
+    #[serde(default)]
+    n: u64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct AdminConfigResponse {
+    server: ServerConfig,
+    log: Config,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct AdminStatsResponse {
+    compaction: CompactionStats,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct AdminFlushResponse {
+    /// Highest offset on disk once the flush returned; `None` while the log is empty.
+    highest_offset: Option<u64>,
+}
+
+const DEFAULT_CONSUME_LIMIT: usize = 100;
+
+// index entries a scan reads per hold of the log lock, and chunks it buffers ahead
+const SCAN_CHUNK_ENTRIES: usize = 1024;
+const SCAN_BUFFER: usize = 4;
+
+// records a tail reads per hold of the log lock, and chunks it buffers ahead
+const TAIL_CHUNK_RECORDS: usize = 256;
+const TAIL_BUFFER: usize = 4;
+
+// how long open HTTPS connections get to finish once shutdown starts
+const TLS_DRAIN_TIMEOUT: Duration = Duration::from_secs(30);
+
+// seconds a producer is told to wait while appends are paused
+const PAUSED_RETRY_AFTER: &str = "1";
+
+pub type SharedLog = Arc<RwLock<Log>>;
+
+#[derive(Clone)]
+pub struct RouterState {
+    log: SharedLog,
+    config: Arc<ServerConfig>,
+    produce_permits: Option<Arc<Semaphore>>,
+}
+
+/// Assembles the server's router around a log from its optional parts.
+pub struct ServerBuilder {
+    log: SharedLog,
+    config: ServerConfig,
+    tls: Option<ServerTlsConfig>,
+}
+
+impl ServerBuilder {
+    pub fn new(log: SharedLog) -> Self {
+        Self {
+            log,
+            config: ServerConfig::default(),
+            tls: None,
+        }
+    }
+
+    pub fn with_config(mut self, config: ServerConfig) -> Self {
+        self.config = config;
+        self
+    }
+
+    /// Serves HTTPS with `tls` from `serve_with_shutdown`; `build` ignores it.
+    pub fn with_tls(mut self, tls: ServerTlsConfig) -> Self {
+        self.tls = Some(tls);
+        self
+    }
+
+    /// Serves the router on `addr`, over HTTPS if `with_tls` was given, until `shutdown`
+    /// completes. The requests in flight then finish, over HTTPS for up to 30 seconds.
+    pub async fn serve_with_shutdown<F>(mut self, addr: SocketAddr, shutdown: F) -> io::Result<()>
+    where
+        F: Future<Output = ()>,
+    {
+        let tls = self.tls.take();
+        let router = self.build();
+        match tls {
+            Some(tls) => {
+                tls::serve_tls_with_shutdown(addr, router, tls, shutdown, Some(TLS_DRAIN_TIMEOUT))
+                    .await
+            }
+            None => serve_with_shutdown(addr, router, shutdown)
+                .await
+                .map_err(io::Error::other),
+        }
+    }
+
+    pub fn build(self) -> Router {
+        let state = RouterState {
+            log: self.log,
+            config: Arc::new(self.config),
+            produce_permits: self
+                .config
+                .max_inflight_produce
+                .map(|n| Arc::new(Semaphore::new(n))),
+        };
+        Router::new()
+            .route("/", post(handle_produce))
+            .route("/", get(handle_consume))
+            .route("/records/:offset/context", get(handle_consume_context))
+            .route("/consume", get(handle_consume_range))
+            .route("/scan", get(handle_scan))
+            .route("/tail", get(handle_tail))
+            .route("/admin/config", get(handle_admin_config))
+            .route("/admin/stats", get(handle_admin_stats))
+            .route("/admin/flush", post(handle_admin_flush))
+            .route("/admin/pause", post(handle_admin_pause))
+            .route("/admin/resume", post(handle_admin_resume))
+            .with_state(state)
+    }
+}
+
+/// Serves the log kept in `dir`, creating the directory if it doesn't exist yet.
+pub fn create_router<P: AsRef<std::path::Path>>(dir: P) -> io::Result<Router> {
+    let config = Config {
+        create_dir: true,
+        ..Default::default()
+    };
+    let log = Log::new(dir, config)?;
+    Ok(create_router_with_log(Arc::new(RwLock::new(log))))
+}
 
-pub fn create_router() -> Router {
-    let log = RouterState::default();
-    Router::new()
-        .route("/", post(handle_produce))
-        .route("/", get(handle_consume))
-        .with_state(log)
+pub fn create_router_with_log(log: SharedLog) -> Router {
+    ServerBuilder::new(log).build()
+}
+
+/// Serves `router` on `addr` until `shutdown` completes, then waits for the requests
+/// already in flight to finish.
+pub async fn serve_with_shutdown<F>(
+    addr: SocketAddr,
+    router: Router,
+    shutdown: F,
+) -> Result<(), hyper::Error>
+where
+    F: Future<Output = ()>,
+{
+    axum::Server::try_bind(&addr)?
+        .serve(router.into_make_service())
+        .with_graceful_shutdown(shutdown)
+        .await
 }
 
 pub async fn handle_produce(
     State(state): State<RouterState>,
     Json(req): Json<ProduceRequest>,
 ) -> Response {
-    let mut log = state.write().expect("posioned write lock");
+    let _permit = match state.produce_permits {
+        Some(ref permits) => {
+            let timeout = state.config.produce_queue_timeout;
+            match tokio::time::timeout(timeout, permits.acquire()).await {
+                Ok(Ok(permit)) => Some(permit),
+                _ => {
+                    let body = "too many in-flight produce requests";
+                    return (StatusCode::SERVICE_UNAVAILABLE, body).into_response();
+                }
+            }
+        }
+        None => None,
+    };
+
+    let mut log = state.log.write().expect("posioned write lock");
     let offset = match log.append(req.record) {
         Ok(offset) => offset,
-        Err(err) => {
-            let body = err.to_string();
-            let status = StatusCode::INTERNAL_SERVER_ERROR;
-            return (status, body).into_response();
-        }
+        Err(err) => return log_error_response(err),
     };
     let resp = ProduceResponse { offset };
     Json(resp).into_response()
@@ -62,16 +271,1116 @@ pub async fn handle_consume(
     State(state): State<RouterState>,
     Json(req): Json<ConsumeRequest>,
 ) -> Response {
-    let log = state.read().expect("poisoned read lock");
-    let record = match log.read(req.offset) {
-        Ok(record) => record,
-        Err(err) => {
-            let (status, body) = match err {
-                LogError::ErrOffsetNotFound => (StatusCode::NOT_FOUND, err.to_string()),
-                LogError::Other => (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()),
+    with_read_log(&state, move |log| {
+        let record = match log.read_at_offset(req.offset) {
+            Ok(Some(record)) => record,
+            Ok(None) => return log_error_response(LogError::ErrOffsetNotFound.into()),
+            Err(err) => return log_error_response(err),
+        };
+        Json(ConsumeResponse { record }).into_response()
+    })
+    .await
+}
+
+pub async fn handle_consume_context(
+    State(state): State<RouterState>,
+    Path(offset): Path<u64>,
+    Query(query): Query<ContextQuery>,
+) -> Response {
+    let max_response_bytes = state.config.max_response_bytes;
+    with_read_log(&state, move |log| {
+        let start = offset.saturating_sub(query.before);
+        let end = offset.saturating_add(query.after);
+        let mut records = Vec::new();
+        let mut bytes = 0;
+        let read = log
+            .read_at_offset(offset)
+            .and_then(|record| record.ok_or_else(|| LogError::ErrOffsetNotFound.into()))
+            .and_then(|_| {
+                // stop at the first record past the cap, keeping at least one
+                log.scan_range(start, end, |record| {
+                    bytes += serialized_len(&record);
+                    if !records.is_empty() && bytes > max_response_bytes {
+                        return false;
+                    }
+                    records.push(record);
+                    true
+                })
+            });
+        if let Err(err) = read {
+            return log_error_response(err);
+        }
+        // clamping to the log's bounds may have shrunk the window
+        let start = records.first().map_or(offset, |record| record.offset);
+        let end = records.last().map_or(offset, |record| record.offset);
+        Json(ContextResponse {
+            start,
+            end,
+            records,
+        })
+        .into_response()
+    })
+    .await
+}
+
+pub async fn handle_consume_range(
+    State(state): State<RouterState>,
+    Query(query): Query<ConsumeRangeQuery>,
+) -> Response {
+    let filter = match query.filter.as_deref().map(str::parse::<Filter>) {
+        None => None,
+        Some(Ok(filter)) => Some(filter),
+        Some(Err(err)) => return (StatusCode::BAD_REQUEST, err.to_string()).into_response(),
+    };
+    // a byte budget replaces the default count limit
+    let limit = match (query.limit, query.max_bytes) {
+        (Some(limit), _) => limit,
+        (None, Some(_)) => usize::MAX,
+        (None, None) => DEFAULT_CONSUME_LIMIT,
+    };
+    let max_bytes = query
+        .max_bytes
+        .map_or(state.config.max_response_bytes, |b| {
+            b.min(state.config.max_response_bytes)
+        });
+
+    let config = state.config.clone();
+    with_read_log(&state, move |log| {
+        let token = match (query.page_token, query.from) {
+            (Some(_), Some(_)) => {
+                let body = "pass either from or page_token, not both";
+                return (StatusCode::BAD_REQUEST, body).into_response();
+            }
+            (Some(token), None) => match token
+                .parse::<PageToken>()
+                .and_then(|t| t.check_expiry(config.page_token_ttl).map(|_| t))
+            {
+                Ok(token) => token,
+                Err(err) => return (StatusCode::BAD_REQUEST, err.to_string()).into_response(),
+            },
+            (None, from) => PageToken::new(from.unwrap_or(0), log.next_offset()),
+        };
+
+        let mut records = Vec::new();
+        let mut next_offset = token.next_offset;
+        if limit > 0 && token.next_offset < token.high_watermark {
+            let mut bytes = 0;
+            let scanned = log.scan_range(token.next_offset, token.high_watermark - 1, |record| {
+                if filter.as_ref().is_some_and(|f| !f.matches(&record)) {
+                    next_offset = record.offset + 1;
+                    return true;
+                }
+                // stop short of the budget, but always make progress
+                let size = serialized_len(&record);
+                if !records.is_empty() && bytes + size > max_bytes {
+                    return false;
+                }
+                bytes += size;
+                next_offset = record.offset + 1;
+                records.push(record);
+                records.len() < limit
+            });
+            match scanned {
+                Ok(()) => {}
+                Err(err) if err.kind() == ErrorKind::NotFound => {}
+                Err(err) => return log_error_response(err),
+            }
+        }
+
+        let next_page_token =
+            (next_offset < token.high_watermark).then(|| token.next(next_offset).to_string());
+        Json(ConsumeRangeResponse {
+            records,
+            next_offset,
+            next_page_token,
+        })
+        .into_response()
+    })
+    .await
+}
+
+/// Runs `read` against the log, answering 504 if it outlasts `ServerConfig::read_timeout`.
+///
+/// A blocking read can't be cancelled, so on timeout it is left to finish on the blocking
+/// pool and only the request is freed.
+async fn with_read_log<F>(state: &RouterState, read: F) -> Response
+where
+    F: FnOnce(&mut Log) -> Response + Send + 'static,
+{
+    // reads flush the store's write buffer, so they need the log exclusively
+    let timeout = match state.config.read_timeout {
+        Some(timeout) => timeout,
+        None => return read(&mut state.log.write().expect("poisoned write lock")),
+    };
+    let log = state.log.clone();
+    let task =
+        tokio::task::spawn_blocking(move || read(&mut log.write().expect("poisoned write lock")));
+    match tokio::time::timeout(timeout, task).await {
+        Ok(Ok(resp)) => resp,
+        Ok(Err(_)) => (StatusCode::INTERNAL_SERVER_ERROR, "read failed").into_response(),
+        Err(_) => (StatusCode::GATEWAY_TIMEOUT, "read timed out").into_response(),
+    }
+}
+
+/// Streams an `offset\tposition\tlength` line for every index entry in `[from, to]`,
+/// both defaulting to the ends of the log, without reading any record bodies.
+///
+/// The log is locked for a chunk of entries at a time rather than for the whole scan.
+pub async fn handle_scan(
+    State(state): State<RouterState>,
+    Query(query): Query<ScanQuery>,
+) -> Response {
+    let to = query.to.unwrap_or(u64::MAX);
+    let read = move |log: &SharedLog, from: u64| {
+        let mut log = log.write().expect("poisoned write lock");
+        log.index_entries(from, to, SCAN_CHUNK_ENTRIES)
+    };
+    // the first chunk is read up front so an error still gets its status
+    let first = match read(&state.log, query.from.unwrap_or(0)) {
+        Ok(entries) => entries,
+        Err(err) => return log_error_response(err),
+    };
+    let (tx, rx) = mpsc::channel::<io::Result<String>>(SCAN_BUFFER);
+    let log = state.log.clone();
+    tokio::task::spawn_blocking(move || {
+        let mut entries = first;
+        while let Some(last) = entries.last().map(|entry| entry.offset) {
+            if tx.blocking_send(Ok(scan_lines(&entries))).is_err() || last >= to {
+                return;
+            }
+            entries = match read(&log, last + 1) {
+                Ok(entries) => entries,
+                Err(err) => {
+                    let _ = tx.blocking_send(Err(err));
+                    return;
+                }
             };
-            return (status, body).into_response();
         }
+    });
+    (
+        [(header::CONTENT_TYPE, "text/plain; charset=utf-8")],
+        StreamBody::new(ReceiverStream::new(rx)),
+    )
+        .into_response()
+}
+
+fn scan_lines(entries: &[IndexEntry]) -> String {
+    entries
+        .iter()
+        .map(|entry| format!("{}\t{}\t{}\n", entry.offset, entry.position, entry.len))
+        .collect()
+}
+
+/// Streams the last `n` records, oldest first, as a JSON line each, then every record
+/// appended after them until the client goes away. An `n` past the start of the log
+/// sends all of it.
+pub async fn handle_tail(
+    State(state): State<RouterState>,
+    Query(query): Query<TailQuery>,
+) -> Response {
+    let start = {
+        let log = state.log.read().expect("poisoned read lock");
+        match log.lowest_offset() {
+            Ok(lowest) => log.next_offset().saturating_sub(query.n).max(lowest),
+            Err(err) => return log_error_response(err),
+        }
+    };
+    let (tx, rx) = mpsc::channel::<io::Result<String>>(TAIL_BUFFER);
+    let log = state.log.clone();
+    tokio::spawn(async move {
+        let notify = log.read().expect("poisoned read lock").append_notify();
+        let mut offset = start;
+        loop {
+            // registered before reading, so an append right after the read still wakes it
+            let notified = notify.notified();
+            let read = read_tail(&mut log.write().expect("poisoned write lock"), &mut offset);
+            match read {
+                Ok(lines) if lines.is_empty() => {
+                    tokio::select! {
+                        _ = notified => continue,
+                        _ = tx.closed() => return,
+                    }
+                }
+                Ok(lines) => {
+                    if tx.send(Ok(lines)).await.is_err() {
+                        return;
+                    }
+                }
+                Err(err) => {
+                    let _ = tx.send(Err(err)).await;
+                    return;
+                }
+            }
+        }
+    });
+    (
+        [(header::CONTENT_TYPE, "application/x-ndjson")],
+        StreamBody::new(ReceiverStream::new(rx)),
+    )
+        .into_response()
+}
+
+// reads up to a chunk of records from `offset` on as JSON lines, moving `offset` past them
+fn read_tail(log: &mut Log, offset: &mut u64) -> io::Result<String> {
+    let next_offset = log.next_offset();
+    let mut lines = String::new();
+    if *offset >= next_offset {
+        return Ok(lines);
+    }
+    let mut read = 0;
+    let mut last = None;
+    log.scan_range(*offset, u64::MAX, |record| {
+        last = Some(record.offset);
+        lines.push_str(&serde_json::to_string(&record).expect("records serialize"));
+        lines.push('\n');
+        read += 1;
+        read < TAIL_CHUNK_RECORDS
+    })?;
+    *offset = match last {
+        Some(last) if read == TAIL_CHUNK_RECORDS => last + 1,
+        // the scan reached the end, skipping any gaps on the way
+        _ => next_offset,
+    };
+    Ok(lines)
+}
+
+/// Reports the configuration the server is running with.
+pub async fn handle_admin_config(State(state): State<RouterState>) -> Response {
+    let log = state.log.read().expect("poisoned read lock").config.clone();
+    Json(AdminConfigResponse {
+        server: *state.config,
+        log,
+    })
+    .into_response()
+}
+
+/// Reports the bytes the log's merges and truncations have read, written and freed.
+pub async fn handle_admin_stats(State(state): State<RouterState>) -> Response {
+    let compaction = state
+        .log
+        .read()
+        .expect("poisoned read lock")
+        .compaction_stats();
+    Json(AdminStatsResponse { compaction }).into_response()
+}
+
+/// Fsyncs the log, e.g. before a snapshot, and reports the highest offset now durable.
+/// Segments with nothing written since their last sync cost little.
+pub async fn handle_admin_flush(State(state): State<RouterState>) -> Response {
+    let mut log = state.log.write().expect("poisoned write lock");
+    let highest_offset = log.sync().and_then(|()| {
+        let empty = log.next_offset() == log.lowest_offset()?;
+        (!empty).then(|| log.highest_offset()).transpose()
+    });
+    match highest_offset {
+        Ok(highest_offset) => Json(AdminFlushResponse { highest_offset }).into_response(),
+        Err(err) => log_error_response(err),
+    }
+}
+
+/// Stops the log accepting produces. Appends already holding the log finish first.
+pub async fn handle_admin_pause(State(state): State<RouterState>) -> StatusCode {
+    state.log.write().expect("poisoned write lock").pause();
+    StatusCode::NO_CONTENT
+}
+
+pub async fn handle_admin_resume(State(state): State<RouterState>) -> StatusCode {
+    state.log.write().expect("poisoned write lock").resume();
+    StatusCode::NO_CONTENT
+}
+
+fn serialized_len(record: &Record) -> usize {
+    serde_json::to_vec(record).map_or(0, |buf| buf.len())
+}
+
+/// Maps a log error to a response, by the `LogError` inside it if there is one and
+/// otherwise by its kind.
+fn log_error_response(err: io::Error) -> Response {
+    let status = match err.get_ref().and_then(|e| e.downcast_ref::<LogError>()) {
+        Some(LogError::ErrOffsetNotFound) => StatusCode::NOT_FOUND,
+        Some(LogError::Rejected { .. }) => StatusCode::UNPROCESSABLE_ENTITY,
+        Some(LogError::Paused) => {
+            let headers = [(header::RETRY_AFTER, PAUSED_RETRY_AFTER)];
+            return (StatusCode::SERVICE_UNAVAILABLE, headers, err.to_string()).into_response();
+        }
+        Some(LogError::Closed | LogError::SegmentClosed { .. }) => StatusCode::CONFLICT,
+        Some(LogError::Other) => StatusCode::INTERNAL_SERVER_ERROR,
+        // a missing offset, or one in a gap, reads as not found
+        None => match err.kind() {
+            ErrorKind::NotFound | ErrorKind::UnexpectedEof => StatusCode::NOT_FOUND,
+            _ => StatusCode::INTERNAL_SERVER_ERROR,
+        },
     };
-    Json(ConsumeResponse { record }).into_response()
+    (status, err.to_string()).into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use serde_json::Value;
+    use tower::ServiceExt;
+
+    use super::*;
+
+    fn empty_state() -> (tempfile::TempDir, RouterState) {
+        empty_state_with_config(Config::default())
+    }
+
+    fn empty_state_with_config(config: Config) -> (tempfile::TempDir, RouterState) {
+        let dir = tempfile::tempdir().unwrap();
+        let log = Log::new(dir.path(), config).unwrap();
+        let state = RouterState {
+            log: Arc::new(RwLock::new(log)),
+            config: Arc::new(ServerConfig::default()),
+            produce_permits: None,
+        };
+        (dir, state)
+    }
+
+    fn state_with_records(n: u64) -> (tempfile::TempDir, RouterState) {
+        let (dir, state) = empty_state();
+        {
+            let mut log = state.log.write().unwrap();
+            for i in 0..n {
+                let record = Record {
+                    value: format!("record {}", i).into_bytes(),
+                    offset: 0,
+                };
+                log.append(record).unwrap();
+            }
+        }
+        (dir, state)
+    }
+
+    fn state_append(state: &RouterState, n: u64) {
+        let mut log = state.log.write().unwrap();
+        for _ in 0..n {
+            let record = Record {
+                value: b"appended".to_vec(),
+                offset: 0,
+            };
+            log.append(record).unwrap();
+        }
+    }
+
+    async fn body_json(resp: Response) -> Value {
+        let body = hyper::body::to_bytes(resp.into_body()).await.unwrap();
+        serde_json::from_slice(&body).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_consume_context() {
+        // a log that doesn't start at 0, so clamping `before` shows in the bounds
+        let (_dir, state) = empty_state_with_config(Config {
+            initial_offset: 1000,
+            ..Default::default()
+        });
+        state_append(&state, 10);
+
+        let query = ContextQuery {
+            before: 5,
+            after: 3,
+        };
+        let resp = handle_consume_context(State(state.clone()), Path(1002), Query(query)).await;
+        assert_eq!(StatusCode::OK, resp.status());
+        let body = body_json(resp).await;
+        assert_eq!(1000, body["start"]);
+        assert_eq!(1005, body["end"]);
+        let records = body["records"].as_array().unwrap();
+        assert_eq!(6, records.len());
+        assert_eq!(1002, records[2]["offset"]);
+
+        let query = ContextQuery {
+            before: 1,
+            after: 5,
+        };
+        let resp = handle_consume_context(State(state.clone()), Path(1008), Query(query)).await;
+        let body = body_json(resp).await;
+        assert_eq!(1007, body["start"]);
+        assert_eq!(1009, body["end"]);
+
+        let query = ContextQuery {
+            before: 1,
+            after: 1,
+        };
+        let resp = handle_consume_context(State(state), Path(1010), Query(query)).await;
+        assert_eq!(StatusCode::NOT_FOUND, resp.status());
+    }
+
+    #[tokio::test]
+    async fn test_serve_with_shutdown() {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let (_dir, state) = state_with_records(1);
+        let router = ServerBuilder::new(state.log.clone()).build();
+        let addr = std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap();
+        let (tx, rx) = tokio::sync::oneshot::channel::<()>();
+        let server = tokio::spawn(serve_with_shutdown(addr, router, async {
+            rx.await.ok();
+        }));
+
+        let mut conn = loop {
+            match tokio::net::TcpStream::connect(addr).await {
+                Ok(conn) => break conn,
+                Err(_) => tokio::time::sleep(Duration::from_millis(10)).await,
+            }
+        };
+        conn.write_all(b"GET /admin/config HTTP/1.1\r\nHost: proglog\r\nConnection: close\r\n\r\n")
+            .await
+            .unwrap();
+        let mut resp = Vec::new();
+        conn.read_to_end(&mut resp).await.unwrap();
+        assert!(resp.starts_with(b"HTTP/1.1 200 OK"));
+
+        tx.send(()).unwrap();
+        tokio::time::timeout(Duration::from_secs(5), server)
+            .await
+            .unwrap()
+            .unwrap()
+            .unwrap();
+        assert!(tokio::net::TcpStream::connect(addr).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_builder_with_log() {
+        let (_dir, state) = state_with_records(3);
+        let router = create_router_with_log(state.log.clone());
+
+        let produce = serde_json::json!({ "record": { "value": "aGVsbG8=" } });
+        let req = hyper::Request::post("/")
+            .header("content-type", "application/json")
+            .body(hyper::Body::from(produce.to_string()))
+            .unwrap();
+        let resp = router.oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::OK, resp.status());
+        assert_eq!(3, body_json(resp).await["offset"]);
+
+        let record = state.log.write().unwrap().read_at_offset(3).unwrap();
+        let record = record.unwrap();
+        assert_eq!(b"hello".to_vec(), record.value);
+    }
+
+    #[tokio::test]
+    async fn test_consume_range_filter() {
+        let (_dir, state) = state_with_records(12);
+
+        // "record 1", "record 10" and "record 11" contain "d 1"
+        let query = ConsumeRangeQuery {
+            from: Some(0),
+            page_token: None,
+            filter: Some("contains:d 1".to_string()),
+            limit: Some(2),
+            max_bytes: None,
+        };
+        let resp = handle_consume_range(State(state.clone()), Query(query)).await;
+        assert_eq!(StatusCode::OK, resp.status());
+        let body = body_json(resp).await;
+        let records = body["records"].as_array().unwrap();
+        let offsets: Vec<u64> = records
+            .iter()
+            .map(|r| r["offset"].as_u64().unwrap())
+            .collect();
+        assert_eq!(vec![1, 10], offsets);
+        assert_eq!(11, body["next_offset"]);
+
+        let query = ConsumeRangeQuery {
+            from: Some(11),
+            page_token: None,
+            filter: Some("contains:d 1".to_string()),
+            limit: Some(2),
+            max_bytes: None,
+        };
+        let resp = handle_consume_range(State(state.clone()), Query(query)).await;
+        let body = body_json(resp).await;
+        assert_eq!(1, body["records"].as_array().unwrap().len());
+        assert_eq!(12, body["next_offset"]);
+
+        let query = ConsumeRangeQuery {
+            from: Some(12),
+            page_token: None,
+            filter: None,
+            limit: None,
+            max_bytes: None,
+        };
+        let resp = handle_consume_range(State(state.clone()), Query(query)).await;
+        let body = body_json(resp).await;
+        assert!(body["records"].as_array().unwrap().is_empty());
+        assert_eq!(12, body["next_offset"]);
+
+        let query = ConsumeRangeQuery {
+            from: Some(0),
+            page_token: None,
+            filter: Some("value == 1".to_string()),
+            limit: None,
+            max_bytes: None,
+        };
+        let resp = handle_consume_range(State(state), Query(query)).await;
+        assert_eq!(StatusCode::BAD_REQUEST, resp.status());
+    }
+
+    #[tokio::test]
+    async fn test_consume_range_response_cap() {
+        let (_dir, mut state) = state_with_records(10);
+        let one = serialized_len(
+            &state
+                .log
+                .write()
+                .unwrap()
+                .read_at_offset(0)
+                .unwrap()
+                .unwrap(),
+        );
+        state.config = Arc::new(ServerConfig {
+            max_response_bytes: one * 3 + one / 2,
+            ..Default::default()
+        });
+
+        let query = ConsumeRangeQuery {
+            from: Some(2),
+            page_token: None,
+            filter: None,
+            limit: None,
+            max_bytes: None,
+        };
+        let resp = handle_consume_range(State(state.clone()), Query(query)).await;
+        let body = body_json(resp).await;
+        let offsets: Vec<u64> = body["records"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|r| r["offset"].as_u64().unwrap())
+            .collect();
+        assert_eq!(vec![2, 3, 4], offsets);
+        assert_eq!(5, body["next_offset"]);
+
+        // a record bigger than the cap is still returned on its own
+        state.config = Arc::new(ServerConfig {
+            max_response_bytes: 1,
+            ..Default::default()
+        });
+        let query = ConsumeRangeQuery {
+            from: Some(5),
+            page_token: None,
+            filter: None,
+            limit: None,
+            max_bytes: None,
+        };
+        let resp = handle_consume_range(State(state.clone()), Query(query)).await;
+        let body = body_json(resp).await;
+        assert_eq!(1, body["records"].as_array().unwrap().len());
+        assert_eq!(6, body["next_offset"]);
+
+        let query = ContextQuery {
+            before: 2,
+            after: 2,
+        };
+        let resp = handle_consume_context(State(state.clone()), Path(5), Query(query)).await;
+        let body = body_json(resp).await;
+        assert_eq!(3, body["start"]);
+        assert_eq!(3, body["end"]);
+
+        // an unbounded `after` stops reading at the cap too
+        let query = ContextQuery {
+            before: 0,
+            after: u64::MAX,
+        };
+        let resp = handle_consume_context(State(state), Path(5), Query(query)).await;
+        let body = body_json(resp).await;
+        assert_eq!(1, body["records"].as_array().unwrap().len());
+        assert_eq!(5, body["end"]);
+    }
+
+    #[tokio::test]
+    async fn test_max_inflight_produce() {
+        let produce = || {
+            Json(ProduceRequest {
+                record: Record {
+                    value: b"hello".to_vec(),
+                    offset: 0,
+                },
+            })
+        };
+
+        let (_dir, mut state) = empty_state();
+        state.produce_permits = Some(Arc::new(Semaphore::new(1)));
+        let permits = state.produce_permits.clone().unwrap();
+
+        // a produce over the limit is rejected straight away
+        let inflight = permits.clone().acquire_owned().await.unwrap();
+        let resp = handle_produce(State(state.clone()), produce()).await;
+        assert_eq!(StatusCode::SERVICE_UNAVAILABLE, resp.status());
+
+        // or waits for a slot when queueing is allowed
+        state.config = Arc::new(ServerConfig {
+            produce_queue_timeout: Duration::from_secs(5),
+            ..Default::default()
+        });
+        let waiting = tokio::spawn(handle_produce(State(state.clone()), produce()));
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        assert!(!waiting.is_finished());
+        drop(inflight);
+        let resp = waiting.await.unwrap();
+        assert_eq!(StatusCode::OK, resp.status());
+        assert_eq!(1, permits.available_permits());
+
+        let resp = handle_produce(State(state), produce()).await;
+        assert_eq!(StatusCode::OK, resp.status());
+    }
+
+    #[tokio::test]
+    async fn test_produce_rejected_by_validator() {
+        let (_dir, state) = state_with_records(1);
+        {
+            let mut log = state.log.write().unwrap();
+            log.set_validator(Arc::new(|record: &Record| {
+                if record.value.len() > 8 {
+                    return Err(format!("value is {} bytes", record.value.len()));
+                }
+                Ok(())
+            }));
+            log.set_validator(Arc::new(|record: &Record| match record.value.first() {
+                Some(b'#') => Err("comment".to_string()),
+                _ => Ok(()),
+            }));
+        }
+        let produce = |value: &[u8]| {
+            Json(ProduceRequest {
+                record: Record {
+                    value: value.to_vec(),
+                    offset: 0,
+                },
+            })
+        };
+
+        let resp = handle_produce(State(state.clone()), produce(b"far too large")).await;
+        assert_eq!(StatusCode::UNPROCESSABLE_ENTITY, resp.status());
+        let resp = handle_produce(State(state.clone()), produce(b"#small")).await;
+        assert_eq!(StatusCode::UNPROCESSABLE_ENTITY, resp.status());
+        assert!(state.log.write().unwrap().read_at_offset(1).is_err());
+
+        let resp = handle_produce(State(state.clone()), produce(b"small")).await;
+        assert_eq!(StatusCode::OK, resp.status());
+        assert_eq!(1, body_json(resp).await["offset"]);
+    }
+
+    #[tokio::test]
+    async fn test_consume_range_max_bytes() {
+        let (_dir, state) = state_with_records(150);
+        let one = serialized_len(
+            &state
+                .log
+                .write()
+                .unwrap()
+                .read_at_offset(0)
+                .unwrap()
+                .unwrap(),
+        );
+
+        let query = ConsumeRangeQuery {
+            from: Some(3),
+            page_token: None,
+            filter: None,
+            limit: None,
+            max_bytes: Some(one * 4 + one / 2),
+        };
+        let resp = handle_consume_range(State(state.clone()), Query(query)).await;
+        let body = body_json(resp).await;
+        let records = body["records"].as_array().unwrap();
+        let offsets: Vec<u64> = records
+            .iter()
+            .map(|r| r["offset"].as_u64().unwrap())
+            .collect();
+        assert_eq!(vec![3, 4, 5, 6], offsets);
+        assert!(records.iter().map(|r| r.to_string().len()).sum::<usize>() <= one * 4 + one / 2);
+        assert_eq!(7, body["next_offset"]);
+
+        // a record over the budget is still returned on its own
+        let query = ConsumeRangeQuery {
+            from: Some(7),
+            page_token: None,
+            filter: None,
+            limit: None,
+            max_bytes: Some(1),
+        };
+        let resp = handle_consume_range(State(state.clone()), Query(query)).await;
+        let body = body_json(resp).await;
+        assert_eq!(1, body["records"].as_array().unwrap().len());
+        assert_eq!(8, body["next_offset"]);
+
+        // without a limit, a large budget is not held to the default count
+        let query = ConsumeRangeQuery {
+            from: Some(0),
+            page_token: None,
+            filter: None,
+            limit: None,
+            max_bytes: Some(one * 200),
+        };
+        let resp = handle_consume_range(State(state), Query(query)).await;
+        let body = body_json(resp).await;
+        assert_eq!(150, body["records"].as_array().unwrap().len());
+        assert_eq!(150, body["next_offset"]);
+    }
+
+    #[tokio::test]
+    async fn test_consume_range_page_token() {
+        let (_dir, state) = state_with_records(10);
+        let page = |from: Option<u64>, page_token: Option<String>| ConsumeRangeQuery {
+            from,
+            page_token,
+            filter: None,
+            limit: Some(4),
+            max_bytes: None,
+        };
+
+        let resp = handle_consume_range(State(state.clone()), Query(page(Some(1), None))).await;
+        let body = body_json(resp).await;
+        assert_eq!(5, body["next_offset"]);
+        let mut token = body["next_page_token"].as_str().unwrap().to_string();
+
+        // records appended mid-pagination stay out of it
+        state_append(&state, 5);
+        let mut offsets = Vec::new();
+        loop {
+            let resp =
+                handle_consume_range(State(state.clone()), Query(page(None, Some(token)))).await;
+            assert_eq!(StatusCode::OK, resp.status());
+            let body = body_json(resp).await;
+            offsets.extend(
+                body["records"]
+                    .as_array()
+                    .unwrap()
+                    .iter()
+                    .map(|r| r["offset"].as_u64().unwrap()),
+            );
+            match body["next_page_token"].as_str() {
+                Some(next) => token = next.to_string(),
+                None => break,
+            }
+        }
+        assert_eq!((5..10).collect::<Vec<u64>>(), offsets);
+
+        let resp = handle_consume_range(
+            State(state.clone()),
+            Query(page(Some(0), Some(PageToken::new(0, 10).to_string()))),
+        )
+        .await;
+        assert_eq!(StatusCode::BAD_REQUEST, resp.status());
+
+        let resp = handle_consume_range(
+            State(state.clone()),
+            Query(page(None, Some("garbage".to_string()))),
+        )
+        .await;
+        assert_eq!(StatusCode::BAD_REQUEST, resp.status());
+
+        let expired = PageToken {
+            issued_at: 0,
+            ..PageToken::new(0, 10)
+        };
+        let resp =
+            handle_consume_range(State(state), Query(page(None, Some(expired.to_string())))).await;
+        assert_eq!(StatusCode::BAD_REQUEST, resp.status());
+    }
+
+    #[tokio::test]
+    async fn test_scan() {
+        // more entries than one chunk, spread over many segments
+        let (_dir, state) = state_with_records(SCAN_CHUNK_ENTRIES as u64 + 100);
+        let router = create_router_with_log(state.log.clone());
+        let scan = |uri: &str| hyper::Request::get(uri).body(hyper::Body::empty()).unwrap();
+        let want = |from: u64, to: u64| {
+            let mut log = state.log.write().unwrap();
+            scan_lines(&log.index_entries(from, to, usize::MAX).unwrap())
+        };
+
+        let resp = router.clone().oneshot(scan("/scan")).await.unwrap();
+        assert_eq!(StatusCode::OK, resp.status());
+        assert_eq!(
+            "text/plain; charset=utf-8",
+            resp.headers()[header::CONTENT_TYPE]
+        );
+        let body = hyper::body::to_bytes(resp.into_body()).await.unwrap();
+        let body = String::from_utf8(body.to_vec()).unwrap();
+        assert_eq!(SCAN_CHUNK_ENTRIES + 100, body.lines().count());
+        assert_eq!(want(0, u64::MAX), body);
+
+        let resp = router
+            .clone()
+            .oneshot(scan("/scan?from=5&to=1100"))
+            .await
+            .unwrap();
+        let body = hyper::body::to_bytes(resp.into_body()).await.unwrap();
+        let body = String::from_utf8(body.to_vec()).unwrap();
+        let offsets: Vec<u64> = body
+            .lines()
+            .map(|line| line.split('\t').next().unwrap().parse().unwrap())
+            .collect();
+        assert_eq!((5..=1100).collect::<Vec<u64>>(), offsets);
+        assert_eq!(want(5, 1100), body);
+
+        let resp = router.oneshot(scan("/scan?from=9&to=3")).await.unwrap();
+        assert_eq!(StatusCode::OK, resp.status());
+        assert!(hyper::body::to_bytes(resp.into_body())
+            .await
+            .unwrap()
+            .is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_tail() {
+        use hyper::body::HttpBody;
+
+        let (_dir, state) = state_with_records(5);
+        let router = create_router_with_log(state.log.clone());
+        let tail = |uri: &str| hyper::Request::get(uri).body(hyper::Body::empty()).unwrap();
+        // reads lines off the endless body until `n` have come
+        async fn offsets(body: &mut axum::body::BoxBody, n: usize) -> Vec<u64> {
+            let mut buf = String::new();
+            while buf.lines().count() < n {
+                let chunk = tokio::time::timeout(Duration::from_secs(5), body.data())
+                    .await
+                    .unwrap()
+                    .unwrap()
+                    .unwrap();
+                buf.push_str(std::str::from_utf8(&chunk).unwrap());
+            }
+            buf.lines()
+                .map(|line| {
+                    let record: Value = serde_json::from_str(line).unwrap();
+                    record["offset"].as_u64().unwrap()
+                })
+                .collect()
+        }
+
+        let resp = router.clone().oneshot(tail("/tail?n=3")).await.unwrap();
+        assert_eq!(StatusCode::OK, resp.status());
+        assert_eq!("application/x-ndjson", resp.headers()[header::CONTENT_TYPE]);
+        let mut body = resp.into_body();
+        assert_eq!(vec![2, 3, 4], offsets(&mut body, 3).await);
+        // live appends follow straight on from the context
+        state_append(&state, 2);
+        assert_eq!(vec![5, 6], offsets(&mut body, 2).await);
+
+        // asking for more than the log holds sends all of it
+        let resp = router.oneshot(tail("/tail?n=100")).await.unwrap();
+        let mut body = resp.into_body();
+        assert_eq!((0..7).collect::<Vec<u64>>(), offsets(&mut body, 7).await);
+        state_append(&state, 1);
+        assert_eq!(vec![7], offsets(&mut body, 1).await);
+    }
+
+    #[tokio::test]
+    async fn test_admin_config() {
+        let config = ServerConfig {
+            max_response_bytes: 1234,
+            max_inflight_produce: Some(3),
+            ..Default::default()
+        };
+        let (_dir, state) = empty_state();
+        let router = ServerBuilder::new(state.log).with_config(config).build();
+
+        let req = hyper::Request::get("/admin/config")
+            .body(hyper::Body::empty())
+            .unwrap();
+        let resp = router.oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::OK, resp.status());
+        let body = body_json(resp).await;
+        assert_eq!(1234, body["server"]["max_response_bytes"]);
+        assert_eq!(3, body["server"]["max_inflight_produce"]);
+        assert_eq!(1024, body["log"]["max_store_bytes"]);
+    }
+
+    #[tokio::test]
+    async fn test_admin_stats() {
+        let (_dir, state) = empty_state_with_config(Config {
+            max_store_bytes: 32,
+            ..Default::default()
+        });
+        state_append(&state, 6);
+        let router = create_router_with_log(state.log.clone());
+        let stats = || hyper::Request::get("/admin/stats").body(hyper::Body::empty());
+
+        let resp = router.clone().oneshot(stats().unwrap()).await.unwrap();
+        assert_eq!(StatusCode::OK, resp.status());
+        assert_eq!(0, body_json(resp).await["compaction"]["bytes_reclaimed"]);
+
+        let reclaimed = {
+            let mut log = state.log.write().unwrap();
+            log.truncate(3).unwrap();
+            log.compaction_stats().bytes_reclaimed
+        };
+        assert!(reclaimed > 0);
+        let resp = router.oneshot(stats().unwrap()).await.unwrap();
+        let body = body_json(resp).await;
+        assert_eq!(reclaimed, body["compaction"]["bytes_reclaimed"]);
+        assert_eq!(0, body["compaction"]["bytes_written"]);
+    }
+
+    #[tokio::test]
+    async fn test_admin_flush() {
+        let (_dir, state) = empty_state();
+        let router = create_router_with_log(state.log.clone());
+        let flush = || {
+            hyper::Request::post("/admin/flush")
+                .body(hyper::Body::empty())
+                .unwrap()
+        };
+
+        let resp = router.clone().oneshot(flush()).await.unwrap();
+        assert_eq!(StatusCode::OK, resp.status());
+        assert!(body_json(resp).await["highest_offset"].is_null());
+
+        let produce = serde_json::json!({ "record": { "value": "aGVsbG8=" } });
+        let req = hyper::Request::post("/")
+            .header("content-type", "application/json")
+            .body(hyper::Body::from(produce.to_string()))
+            .unwrap();
+        let resp = router.clone().oneshot(req).await.unwrap();
+        let offset = body_json(resp).await["offset"].clone();
+
+        // flushing again with nothing new written reports the same offset
+        for _ in 0..2 {
+            let resp = router.clone().oneshot(flush()).await.unwrap();
+            assert_eq!(StatusCode::OK, resp.status());
+            assert_eq!(offset, body_json(resp).await["highest_offset"]);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_read_timeout() {
+        let (_dir, mut state) = state_with_records(3);
+        state.config = Arc::new(ServerConfig {
+            read_timeout: Some(Duration::from_millis(50)),
+            ..Default::default()
+        });
+        let consume = || Json(ConsumeRequest { offset: 1 });
+
+        let resp = handle_consume(State(state.clone()), consume()).await;
+        assert_eq!(StatusCode::OK, resp.status());
+
+        // a writer holding the lock stands in for a read stuck on a slow disk
+        let log = state.log.clone();
+        let writer = std::thread::spawn(move || {
+            let _guard = log.write().unwrap();
+            std::thread::sleep(Duration::from_millis(300));
+        });
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        let started = std::time::Instant::now();
+        let resp = handle_consume(State(state.clone()), consume()).await;
+        assert_eq!(StatusCode::GATEWAY_TIMEOUT, resp.status());
+        assert!(started.elapsed() < Duration::from_millis(250));
+
+        let query = ConsumeRangeQuery {
+            from: Some(0),
+            page_token: None,
+            filter: None,
+            limit: None,
+            max_bytes: None,
+        };
+        let resp = handle_consume_range(State(state.clone()), Query(query)).await;
+        assert_eq!(StatusCode::GATEWAY_TIMEOUT, resp.status());
+
+        writer.join().unwrap();
+        let resp = handle_consume(State(state), consume()).await;
+        assert_eq!(StatusCode::OK, resp.status());
+    }
+
+    #[tokio::test]
+    async fn test_pause_resume() {
+        let (_dir, state) = state_with_records(1);
+        let router = create_router_with_log(state.log.clone());
+        let post = |uri: &str, body: hyper::Body| {
+            hyper::Request::post(uri)
+                .header("content-type", "application/json")
+                .body(body)
+                .unwrap()
+        };
+        let produce = || {
+            let produce = serde_json::json!({ "record": { "value": "aGVsbG8=" } });
+            post("/", hyper::Body::from(produce.to_string()))
+        };
+
+        let resp = router
+            .clone()
+            .oneshot(post("/admin/pause", hyper::Body::empty()))
+            .await;
+        assert_eq!(StatusCode::NO_CONTENT, resp.unwrap().status());
+        assert!(state.log.read().unwrap().is_paused());
+
+        let resp = router.clone().oneshot(produce()).await.unwrap();
+        assert_eq!(StatusCode::SERVICE_UNAVAILABLE, resp.status());
+        assert_eq!("1", resp.headers()[header::RETRY_AFTER]);
+
+        // consumes keep working
+        let resp = handle_consume(State(state.clone()), Json(ConsumeRequest { offset: 0 })).await;
+        assert_eq!(StatusCode::OK, resp.status());
+
+        let resp = router
+            .clone()
+            .oneshot(post("/admin/resume", hyper::Body::empty()))
+            .await;
+        assert_eq!(StatusCode::NO_CONTENT, resp.unwrap().status());
+        let resp = router.oneshot(produce()).await.unwrap();
+        assert_eq!(StatusCode::OK, resp.status());
+        assert_eq!(1, body_json(resp).await["offset"]);
+    }
+
+    #[tokio::test]
+    async fn test_produce_after_close() {
+        let (_dir, state) = state_with_records(1);
+        state.log.write().unwrap().close().unwrap();
+        let req = ProduceRequest {
+            record: Record {
+                value: b"hello".to_vec(),
+                offset: 0,
+            },
+        };
+        let resp = handle_produce(State(state.clone()), Json(req)).await;
+        assert_eq!(StatusCode::CONFLICT, resp.status());
+
+        // reads from the closed segments conflict the same way rather than failing
+        let resp = handle_consume(State(state.clone()), Json(ConsumeRequest { offset: 0 })).await;
+        assert_eq!(StatusCode::CONFLICT, resp.status());
+        let router = create_router_with_log(state.log.clone());
+        let req = hyper::Request::get("/records/0/context")
+            .body(hyper::Body::empty())
+            .unwrap();
+        let resp = router.oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::CONFLICT, resp.status());
+    }
+
+    #[tokio::test]
+    async fn test_records_survive_restart() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("log");
+        let json = |body: Value| hyper::Body::from(body.to_string());
+
+        let router = create_router(&path).unwrap();
+        for value in ["aGVsbG8=", "d29ybGQ="] {
+            let req = hyper::Request::post("/")
+                .header("content-type", "application/json")
+                .body(json(serde_json::json!({ "record": { "value": value } })))
+                .unwrap();
+            let resp = router.clone().oneshot(req).await.unwrap();
+            assert_eq!(StatusCode::OK, resp.status());
+        }
+        drop(router);
+
+        let router = create_router(&path).unwrap();
+        let consume = |offset: u64| {
+            hyper::Request::get("/")
+                .header("content-type", "application/json")
+                .body(json(serde_json::json!({ "offset": offset })))
+                .unwrap()
+        };
+        let resp = router.clone().oneshot(consume(1)).await.unwrap();
+        assert_eq!(StatusCode::OK, resp.status());
+        assert_eq!("d29ybGQ=", body_json(resp).await["record"]["value"]);
+        let resp = router.oneshot(consume(2)).await.unwrap();
+        assert_eq!(StatusCode::NOT_FOUND, resp.status());
+    }
 }
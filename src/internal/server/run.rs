@@ -0,0 +1,167 @@
+use std::{future::Future, io, net::SocketAddr};
+
+use tokio::sync::watch;
+
+use super::{config::ServerConfig, grpc, tls::ServerTlsConfig, ServerBuilder, SharedLog};
+
+/// Where and how [`run`] serves the log over each protocol.
+#[derive(Debug, Clone)]
+pub struct RunConfig {
+    /// Address the HTTP server listens on.
+    pub http_addr: SocketAddr,
+    /// Address the gRPC server listens on.
+    pub grpc_addr: SocketAddr,
+    pub server: ServerConfig,
+    /// Serves HTTP over TLS when given; gRPC stays plaintext.
+    pub tls: Option<ServerTlsConfig>,
+}
+
+impl RunConfig {
+    pub fn new(http_addr: SocketAddr, grpc_addr: SocketAddr) -> Self {
+        Self {
+            http_addr,
+            grpc_addr,
+            server: ServerConfig::default(),
+            tls: None,
+        }
+    }
+}
+
+/// Serves `log` over HTTP and gRPC at once until `shutdown` completes or either server
+/// stops, which shuts the other one down too. The log is closed once both have stopped.
+///
+/// Returns the HTTP server's error if it failed, otherwise the gRPC server's, otherwise
+/// any error closing the log.
+pub async fn run<F>(log: SharedLog, config: RunConfig, shutdown: F) -> io::Result<()>
+where
+    F: Future<Output = ()>,
+{
+    let (stop, stopped) = watch::channel(false);
+    let wait = |mut stopped: watch::Receiver<bool>| async move {
+        // an error means the sender is gone, which only happens once both are done
+        let _ = stopped.wait_for(|&stop| stop).await;
+    };
+
+    let mut builder = ServerBuilder::new(log.clone()).with_config(config.server);
+    if let Some(tls) = config.tls {
+        builder = builder.with_tls(tls);
+    }
+    let http = async {
+        let res = builder
+            .serve_with_shutdown(config.http_addr, wait(stopped.clone()))
+            .await;
+        stop.send_replace(true);
+        res
+    };
+    let grpc = async {
+        let res = grpc::serve_with_shutdown(
+            config.grpc_addr,
+            log.clone(),
+            &config.server,
+            wait(stopped.clone()),
+        )
+        .await
+        .map_err(io::Error::other);
+        stop.send_replace(true);
+        res
+    };
+    let signal = async {
+        tokio::select! {
+            _ = shutdown => {
+                stop.send_replace(true);
+            }
+            _ = wait(stopped.clone()) => {}
+        }
+    };
+    let (http, grpc, ()) = tokio::join!(http, grpc, signal);
+
+    // trims the index and flushes the store so a restart finds every record
+    let closed = log.write().expect("poisoned write lock").close();
+    http.and(grpc).and(closed)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::{Arc, RwLock},
+        time::Duration,
+    };
+
+    use crate::log::{config::Config, log::Log};
+    use crate::server::log::{log_client::LogClient, ProduceRequest, Record};
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_run() {
+        let dir = tempfile::tempdir().unwrap();
+        let log = Log::new(dir.path(), Config::default()).unwrap();
+        let log = Arc::new(RwLock::new(log));
+        let config = RunConfig::new(free_addr(), free_addr());
+        let (tx, rx) = tokio::sync::oneshot::channel::<()>();
+        let server = tokio::spawn(run(log.clone(), config.clone(), async {
+            rx.await.ok();
+        }));
+
+        // produced over gRPC
+        let mut client = loop {
+            match LogClient::connect(format!("http://{}", config.grpc_addr)).await {
+                Ok(client) => break client,
+                Err(_) => tokio::time::sleep(Duration::from_millis(10)).await,
+            }
+        };
+        let req = ProduceRequest {
+            record: Some(Record {
+                value: b"over grpc".to_vec(),
+                offset: 0,
+            }),
+        };
+        let offset = client.produce(req).await.unwrap().into_inner().offset;
+
+        // and consumed straight away over HTTP
+        let req = hyper::Request::get(format!("http://{}/", config.http_addr))
+            .header("content-type", "application/json")
+            .body(hyper::Body::from(format!(r#"{{"offset":{}}}"#, offset)))
+            .unwrap();
+        let resp = hyper::Client::new().request(req).await.unwrap();
+        assert_eq!(hyper::StatusCode::OK, resp.status());
+        let body = hyper::body::to_bytes(resp.into_body()).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(offset, body["record"]["offset"]);
+        assert_eq!("b3ZlciBncnBj", body["record"]["value"]);
+
+        tx.send(()).unwrap();
+        tokio::time::timeout(Duration::from_secs(5), server)
+            .await
+            .unwrap()
+            .unwrap()
+            .unwrap();
+        assert!(log.read().unwrap().is_closed());
+    }
+
+    #[tokio::test]
+    async fn test_run_bind_error() {
+        let dir = tempfile::tempdir().unwrap();
+        let log = Log::new(dir.path(), Config::default()).unwrap();
+        let log = Arc::new(RwLock::new(log));
+        let taken = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let config = RunConfig::new(free_addr(), taken.local_addr().unwrap());
+
+        // the gRPC server can't bind, which stops the HTTP one without a shutdown signal
+        let res = tokio::time::timeout(
+            Duration::from_secs(5),
+            run(log.clone(), config, std::future::pending()),
+        )
+        .await
+        .unwrap();
+        assert!(res.is_err());
+        assert!(log.read().unwrap().is_closed());
+    }
+
+    fn free_addr() -> SocketAddr {
+        std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+    }
+}
@@ -1,13 +1,106 @@
-use std::net::SocketAddr;
+use std::{
+    net::SocketAddr,
+    path::PathBuf,
+    sync::{Arc, RwLock},
+};
 
-use proglog::server;
+use clap::Parser;
+use proglog::{
+    log::{config::Config, log::Log},
+    server,
+};
+
+#[derive(Debug, Parser)]
+struct Args {
+    /// Address the HTTP server listens on.
+    #[arg(long, env = "PROGLOG_ADDR", default_value = "127.0.0.1:8080")]
+    addr: SocketAddr,
+    /// Address the gRPC server listens on.
+    #[arg(long, env = "PROGLOG_GRPC_ADDR", default_value = "127.0.0.1:8081")]
+    grpc_addr: SocketAddr,
+    /// Directory the log is kept in, created if it doesn't exist yet.
+    #[arg(long, env = "PROGLOG_DATA_DIR", default_value = "data")]
+    data_dir: PathBuf,
+    /// TOML or JSON file to read the log config from.
+    #[arg(long)]
+    config: Option<PathBuf>,
+    /// PEM certificate chain to serve HTTPS with; plain HTTP without it.
+    #[arg(long, env = "PROGLOG_TLS_CERT", requires = "tls_key")]
+    tls_cert: Option<PathBuf>,
+    /// PEM private key for `--tls-cert`.
+    #[arg(long, env = "PROGLOG_TLS_KEY", requires = "tls_cert")]
+    tls_key: Option<PathBuf>,
+    /// PEM CA bundle every HTTPS client must present a certificate signed by.
+    #[arg(long, env = "PROGLOG_TLS_CLIENT_CA", requires = "tls_cert")]
+    tls_client_ca: Option<PathBuf>,
+}
 
 #[tokio::main]
 async fn main() {
-    let router = server::create_router();
-    let addr = SocketAddr::from(([127, 0, 0, 1], 8080));
-    axum::Server::bind(&addr)
-        .serve(router.into_make_service())
-        .await
-        .expect("can not start the server");
+    let args = Args::parse();
+    let mut config = match args.config {
+        Some(path) => Config::from_file(path).expect("can not load the config"),
+        None => Config::default(),
+    };
+    // the server always creates its data directory
+    config.create_dir = true;
+    let log = Log::new(&args.data_dir, config).expect("can not open the log");
+    let log = Arc::new(RwLock::new(log));
+    let tasks = Log::spawn_background_tasks(&log);
+
+    let scheme = if args.tls_cert.is_some() {
+        "HTTPS"
+    } else {
+        "HTTP"
+    };
+    let mut run_config = server::RunConfig::new(args.addr, args.grpc_addr);
+    if let (Some(cert), Some(key)) = (args.tls_cert, args.tls_key) {
+        run_config.tls = Some(tls_config(cert, key, args.tls_client_ca));
+    }
+    println!(
+        "serving {} over {} on {} and gRPC on {}",
+        args.data_dir.display(),
+        scheme,
+        args.addr,
+        args.grpc_addr
+    );
+    // closes the log once both servers have stopped, whichever stopped them
+    let res = server::run(log, run_config, shutdown_signal()).await;
+    if let Some(tasks) = tasks {
+        // wakes the tasks so they see the log is closed
+        tasks.thread().unpark();
+        tasks.join().expect("background tasks panicked");
+    }
+    res.expect("can not serve the log");
+}
+
+fn tls_config(cert: PathBuf, key: PathBuf, client_ca: Option<PathBuf>) -> server::ServerTlsConfig {
+    let config = server::ServerTlsConfig::new(cert, key);
+    match client_ca {
+        Some(ca) => config.with_client_auth(ca),
+        None => config,
+    }
+}
+
+// resolves on Ctrl-C, or on SIGTERM where there is one
+async fn shutdown_signal() {
+    let ctrl_c = async {
+        tokio::signal::ctrl_c()
+            .await
+            .expect("can not listen for Ctrl-C");
+    };
+    #[cfg(unix)]
+    let terminate = async {
+        tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())
+            .expect("can not listen for SIGTERM")
+            .recv()
+            .await;
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => {},
+        _ = terminate => {},
+    }
 }